@@ -1,6 +1,14 @@
+use crate::class::{RoxClass, RoxInstance};
+use crate::error::RoxError;
 use crate::function::RoxFunction;
+use crate::gc::{self, Trace};
+use crate::list::{self, List};
+use crate::map::{self, MapRef};
 use crate::token::{Literal, Token};
+use crate::vm::Closure;
+use std::cell::RefCell;
 use std::fmt;
+use std::rc::Rc;
 
 pub enum UnaryOperator {
     Bang,
@@ -9,10 +17,16 @@ pub enum UnaryOperator {
 
 #[derive(Clone)]
 pub enum Value {
-    String_(String),
+    String_(Rc<str>),
     Bool(bool),
     Number(f64),
     Callable(RoxFunction),
+    Class(Rc<RoxClass>),
+    Instance(Rc<RefCell<RoxInstance>>),
+    /// A function compiled for the bytecode VM.
+    Closure(Rc<Closure>),
+    List(List),
+    Map(MapRef),
     Nil,
 }
 
@@ -29,11 +43,133 @@ impl From<Literal> for Value {
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self)
+        self.write(f, false, &mut Vec::new())
+    }
+}
+
+/// Formats values as `Value::repr` does.
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, true, &mut Vec::new())
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.equals(other)
+    }
+}
+
+impl Trace for Value {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        match self {
+            Value::Callable(function) => function.trace(visit),
+            Value::Class(class) => visit(gc::address(class)),
+            Value::Instance(instance) => visit(gc::address(instance)),
+            Value::Closure(closure) => visit(gc::address(closure)),
+            Value::List(list) => visit(gc::address(list)),
+            Value::Map(map) => visit(gc::address(map)),
+            Value::String_(_) | Value::Bool(_) | Value::Number(_) | Value::Nil => {}
+        }
     }
 }
 
 impl Value {
+    /// How the value is written in source, as far as it can be: like its display form, but
+    /// with strings quoted and escaped so they can be told apart from other values.
+    pub fn repr(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    /// Reads `self[index]`.
+    pub fn get_index(&self, index: &Value) -> Result<Value, String> {
+        match self {
+            Value::List(list) => list::get(list, index),
+            Value::Map(map) => map::get(map, index),
+            _ => Err("Only lists and maps can be indexed.".to_string()),
+        }
+    }
+
+    /// Performs `self[index] = value`.
+    pub fn set_index(&self, index: &Value, value: Value) -> Result<(), String> {
+        match self {
+            Value::List(list) => list::set(list, index, value),
+            Value::Map(map) => map::set(map, index, value),
+            _ => Err("Only lists and maps can be indexed.".to_string()),
+        }
+    }
+
+    /// Writes the display form, or with `repr` the repr form. `containers` holds the lists
+    /// and maps being written further out, so one that contains itself is cut off instead of
+    /// recursing forever.
+    fn write(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        repr: bool,
+        containers: &mut Vec<usize>,
+    ) -> fmt::Result {
+        match self {
+            Value::String_(s) if repr => {
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\t' => write!(f, "\\t")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Value::String_(s) => write!(f, "{}", s),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) if n.is_nan() => write!(f, "nan"),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Callable(RoxFunction::Native { .. }) => write!(f, "<native fn>"),
+            Value::Callable(RoxFunction::User { name, .. }) => write!(f, "<fn {}>", name.lexeme),
+            Value::Class(class) => write!(f, "{}", class.name),
+            Value::Instance(instance) => write!(f, "{} instance", instance.borrow().class.name),
+            Value::Closure(closure) => write!(f, "{}", closure.function),
+            Value::List(list) => {
+                let address = gc::address(list);
+                if containers.contains(&address) {
+                    return write!(f, "[...]");
+                }
+                containers.push(address);
+                write!(f, "[")?;
+                for (i, element) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    // Elements are always shown in repr form, so `["1"]` and `[1]` differ.
+                    element.write(f, true, containers)?;
+                }
+                containers.pop();
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                let address = gc::address(map);
+                if containers.contains(&address) {
+                    return write!(f, "{{...}}");
+                }
+                containers.push(address);
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}: ", Value::from(key))?;
+                    value.write(f, true, containers)?;
+                }
+                containers.pop();
+                write!(f, "}}")
+            }
+            Value::Nil => write!(f, "nil"),
+        }
+    }
+
     pub fn equals(&self, other: &Value) -> bool {
         match (self, other) {
             (Value::Nil, Value::Nil) => true,
@@ -41,79 +177,228 @@ impl Value {
             (Value::Nil, _) => false,
             (Value::Bool(left), Value::Bool(right)) => left == right,
             (Value::Number(left), Value::Number(right)) => left == right,
-            (Value::String_(left), Value::String_(right)) => left.eq(right),
+            (Value::String_(left), Value::String_(right)) => {
+                Rc::ptr_eq(left, right) || left == right
+            }
+            (Value::Callable(left), Value::Callable(right)) => left.ptr_eq(right),
+            (Value::Class(left), Value::Class(right)) => Rc::ptr_eq(left, right),
+            (Value::Instance(left), Value::Instance(right)) => Rc::ptr_eq(left, right),
+            (Value::Closure(left), Value::Closure(right)) => Rc::ptr_eq(left, right),
+            (Value::List(left), Value::List(right)) => Rc::ptr_eq(left, right),
+            (Value::Map(left), Value::Map(right)) => Rc::ptr_eq(left, right),
             _ => false,
         }
     }
 }
 
+/// Variable-like expressions carry the number of scopes between their use and the
+/// declaration they refer to. The resolver fills it in; `None` means a global.
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expr {
     Literal(Literal),
     Unary(Token, Box<Expr>),
     Binary(Box<Expr>, Token, Box<Expr>),
     Grouping(Box<Expr>),
-    Var(Token),
-    Assign(Token, Box<Expr>),
+    Var(Token, Option<usize>),
+    Assign(Token, Box<Expr>, Option<usize>),
     Logical(Box<Expr>, Token, Box<Expr>),
     Call(Box<Expr>, Token, Vec<Expr>),
+    Get(Box<Expr>, Token),
+    Set(Box<Expr>, Token, Box<Expr>),
+    This(Token, Option<usize>),
+    Super(Token, Token, Option<usize>),
+    /// A list literal, with its closing bracket.
+    List(Token, Vec<Expr>),
+    /// `fun (params) { body }`, with the `fun` keyword.
+    Lambda(Token, Vec<Token>, Vec<Stmt>),
+    /// A map literal's keys and values, with its closing brace.
+    Map(Token, Vec<(Expr, Expr)>),
+    /// `object[index]`, with the closing bracket.
+    Index(Box<Expr>, Token, Box<Expr>),
+    /// `object[index] = value`.
+    SetIndex(Box<Expr>, Token, Box<Expr>, Box<Expr>),
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Stmt {
-    Print(Expr),
+    /// A value to print, with the `print` keyword.
+    Print(Token, Expr),
     Expression(Expr),
     Block(Vec<Stmt>),
     Var(Token, Option<Expr>),
     If(Expr, Box<Stmt>, Option<Box<Stmt>>),
-    While(Expr, Box<Stmt>),
+    /// A condition, a body, and for desugared `for` loops the increment, which `continue`
+    /// still runs.
+    While(Expr, Box<Stmt>, Option<Expr>),
     Function(Token, Vec<Token>, Vec<Stmt>),
+    Class(Token, Option<Expr>, Vec<Stmt>),
+    Return(Token, Option<Expr>),
+    Break(Token),
+    Continue(Token),
+    Throw(Token, Expr),
+    /// The `try` keyword, the guarded block, the `catch` clause's variable and block, and the
+    /// `finally` block. At least one of the clauses is present.
+    Try(
+        Token,
+        Vec<Stmt>,
+        Option<(Token, Vec<Stmt>)>,
+        Option<Vec<Stmt>>,
+    ),
 }
 
 pub trait StmtVisitor<Value> {
-    fn execute(&mut self, stmt: Stmt) {
+    fn execute(&mut self, stmt: Stmt) -> Result<(), RoxError> {
         match stmt {
             Stmt::Expression(expr) => self.visit_expr_stmt(expr),
-            Stmt::Print(expr) => self.visit_print_stmt(expr),
+            Stmt::Print(keyword, expr) => self.visit_print_stmt(keyword, expr),
             Stmt::Var(token, expr) => self.visit_var_stmt(token, expr),
             Stmt::Block(stmts) => self.visit_block_stmt(stmts),
             Stmt::If(expr, then_stmt, else_stmt) => self.visit_if_stmt(expr, then_stmt, else_stmt),
-            Stmt::While(expr, body_stmt) => self.visit_while_stmt(expr, body_stmt),
+            Stmt::While(expr, body_stmt, increment) => {
+                self.visit_while_stmt(expr, body_stmt, increment)
+            }
             Stmt::Function(name, params, body) => self.visit_function_stmt(name, params, body),
+            Stmt::Class(name, superclass, methods) => {
+                self.visit_class_stmt(name, superclass, methods)
+            }
+            Stmt::Return(keyword, value) => self.visit_return_stmt(keyword, value),
+            Stmt::Break(keyword) => self.visit_break_stmt(keyword),
+            Stmt::Continue(keyword) => self.visit_continue_stmt(keyword),
+            Stmt::Throw(keyword, value) => self.visit_throw_stmt(keyword, value),
+            Stmt::Try(_, body, catch, finally) => self.visit_try_stmt(body, catch, finally),
         }
     }
 
-    fn visit_expr_stmt(&mut self, stmt_expr: Expr);
-    fn visit_print_stmt(&mut self, stmt_expr: Expr);
-    fn visit_var_stmt(&mut self, token: Token, stmt_expr: Option<Expr>);
-    fn visit_block_stmt(&mut self, statements: Vec<Stmt>);
-    fn visit_if_stmt(&mut self, expr: Expr, then_stmt: Box<Stmt>, else_stmt: Option<Box<Stmt>>);
-    fn visit_while_stmt(&mut self, expr: Expr, body_stmt: Box<Stmt>);
-    fn visit_function_stmt(&mut self, name: Token, params: Vec<Token>, body: Vec<Stmt>);
+    fn visit_expr_stmt(&mut self, stmt_expr: Expr) -> Result<(), RoxError>;
+    fn visit_print_stmt(&mut self, keyword: Token, stmt_expr: Expr) -> Result<(), RoxError>;
+    fn visit_var_stmt(&mut self, token: Token, stmt_expr: Option<Expr>) -> Result<(), RoxError>;
+    fn visit_block_stmt(&mut self, statements: Vec<Stmt>) -> Result<(), RoxError>;
+    fn visit_if_stmt(
+        &mut self,
+        expr: Expr,
+        then_stmt: Box<Stmt>,
+        else_stmt: Option<Box<Stmt>>,
+    ) -> Result<(), RoxError>;
+    fn visit_while_stmt(
+        &mut self,
+        expr: Expr,
+        body_stmt: Box<Stmt>,
+        increment: Option<Expr>,
+    ) -> Result<(), RoxError>;
+    fn visit_function_stmt(
+        &mut self,
+        name: Token,
+        params: Vec<Token>,
+        body: Vec<Stmt>,
+    ) -> Result<(), RoxError>;
+    fn visit_class_stmt(
+        &mut self,
+        name: Token,
+        superclass: Option<Expr>,
+        methods: Vec<Stmt>,
+    ) -> Result<(), RoxError>;
+    fn visit_return_stmt(&mut self, keyword: Token, value: Option<Expr>) -> Result<(), RoxError>;
+    fn visit_break_stmt(&mut self, keyword: Token) -> Result<(), RoxError>;
+    fn visit_continue_stmt(&mut self, keyword: Token) -> Result<(), RoxError>;
+    fn visit_throw_stmt(&mut self, keyword: Token, value: Expr) -> Result<(), RoxError>;
+    fn visit_try_stmt(
+        &mut self,
+        body: Vec<Stmt>,
+        catch: Option<(Token, Vec<Stmt>)>,
+        finally: Option<Vec<Stmt>>,
+    ) -> Result<(), RoxError>;
 }
 
 pub trait ExprVisitor<Value> {
-    fn evaluate(&mut self, expr: Expr) -> Value {
+    fn evaluate(&mut self, expr: Expr) -> Result<Value, RoxError> {
         match expr {
             Expr::Literal(l) => self.visit_literal_expr(l),
             Expr::Unary(op, r) => self.visit_unary_expr(op, r),
             Expr::Binary(l, op, r) => self.visit_binary_expr(l, op, r),
             Expr::Grouping(g) => self.visit_grouping_expr(g),
-            Expr::Var(t) => self.visit_var_expr(t),
-            Expr::Assign(t, expr) => self.visit_assignment_expr(t, expr),
+            Expr::Var(t, d) => self.visit_var_expr(t, d),
+            Expr::Assign(t, expr, d) => self.visit_assignment_expr(t, expr, d),
             Expr::Logical(l, op, r) => self.visit_logical_expr(l, op, r),
             Expr::Call(c, p, a) => self.visit_call_expr(c, p, a),
+            Expr::Get(o, n) => self.visit_get_expr(o, n),
+            Expr::Set(o, n, v) => self.visit_set_expr(o, n, v),
+            Expr::This(k, d) => self.visit_this_expr(k, d),
+            Expr::Super(k, m, d) => self.visit_super_expr(k, m, d),
+            Expr::List(b, e) => self.visit_list_expr(b, e),
+            Expr::Lambda(k, p, b) => self.visit_lambda_expr(k, p, b),
+            Expr::Map(b, e) => self.visit_map_expr(b, e),
+            Expr::Index(o, b, i) => self.visit_index_expr(o, b, i),
+            Expr::SetIndex(o, b, i, v) => self.visit_set_index_expr(o, b, i, v),
         }
     }
 
-    fn visit_literal_expr(&mut self, literal: Literal) -> Value;
-    fn visit_grouping_expr(&mut self, grouping_expr: Box<Expr>) -> Value;
-    fn visit_unary_expr(&mut self, operator: Token, right: Box<Expr>) -> Value;
-    fn visit_binary_expr(&mut self, left: Box<Expr>, operator: Token, right: Box<Expr>) -> Value;
-    fn visit_var_expr(&mut self, name: Token) -> Value;
-    fn visit_assignment_expr(&mut self, name: Token, expr: Box<Expr>) -> Value;
-    fn visit_logical_expr(&mut self, left: Box<Expr>, operator: Token, right: Box<Expr>) -> Value;
-    fn visit_call_expr(&mut self, callee: Box<Expr>, paren: Token, args: Vec<Expr>) -> Value;
+    fn visit_literal_expr(&mut self, literal: Literal) -> Result<Value, RoxError>;
+    fn visit_grouping_expr(&mut self, grouping_expr: Box<Expr>) -> Result<Value, RoxError>;
+    fn visit_unary_expr(&mut self, operator: Token, right: Box<Expr>) -> Result<Value, RoxError>;
+    fn visit_binary_expr(
+        &mut self,
+        left: Box<Expr>,
+        operator: Token,
+        right: Box<Expr>,
+    ) -> Result<Value, RoxError>;
+    fn visit_var_expr(&mut self, name: Token, depth: Option<usize>) -> Result<Value, RoxError>;
+    fn visit_assignment_expr(
+        &mut self,
+        name: Token,
+        expr: Box<Expr>,
+        depth: Option<usize>,
+    ) -> Result<Value, RoxError>;
+    fn visit_logical_expr(
+        &mut self,
+        left: Box<Expr>,
+        operator: Token,
+        right: Box<Expr>,
+    ) -> Result<Value, RoxError>;
+    fn visit_call_expr(
+        &mut self,
+        callee: Box<Expr>,
+        paren: Token,
+        args: Vec<Expr>,
+    ) -> Result<Value, RoxError>;
+    fn visit_get_expr(&mut self, object: Box<Expr>, name: Token) -> Result<Value, RoxError>;
+    fn visit_set_expr(
+        &mut self,
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+    ) -> Result<Value, RoxError>;
+    fn visit_this_expr(&mut self, keyword: Token, depth: Option<usize>) -> Result<Value, RoxError>;
+    fn visit_super_expr(
+        &mut self,
+        keyword: Token,
+        method: Token,
+        depth: Option<usize>,
+    ) -> Result<Value, RoxError>;
+    fn visit_list_expr(&mut self, bracket: Token, elements: Vec<Expr>) -> Result<Value, RoxError>;
+    fn visit_lambda_expr(
+        &mut self,
+        keyword: Token,
+        params: Vec<Token>,
+        body: Vec<Stmt>,
+    ) -> Result<Value, RoxError>;
+    fn visit_map_expr(
+        &mut self,
+        brace: Token,
+        entries: Vec<(Expr, Expr)>,
+    ) -> Result<Value, RoxError>;
+    fn visit_index_expr(
+        &mut self,
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    ) -> Result<Value, RoxError>;
+    fn visit_set_index_expr(
+        &mut self,
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    ) -> Result<Value, RoxError>;
     fn is_truthy(&mut self, value: Value) -> bool;
     fn is_equal(&mut self, a: Value, b: Value) -> bool;
 }
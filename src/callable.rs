@@ -1,5 +1,5 @@
 use crate::ast::Value;
-use crate::Interpreter;
+use crate::interpreter::Interpreter;
 
 pub trait RoxCallable {
     fn call(interpreter: Interpreter, arguments: Vec<Value>);
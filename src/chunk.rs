@@ -0,0 +1,312 @@
+use crate::ast::Value;
+use crate::token::Token;
+use std::fmt;
+use std::rc::Rc;
+
+/// One VM instruction. Operands are indexes into the chunk's constant pool, stack slots,
+/// upvalue slots or jump distances, kept to 16 bits so an instruction stays four bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OpCode {
+    Constant(u16),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u16),
+    SetLocal(u16),
+    GetGlobal(u16),
+    DefineGlobal(u16),
+    SetGlobal(u16),
+    GetUpvalue(u16),
+    SetUpvalue(u16),
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    /// Skips forward over the given number of instructions.
+    Jump(u16),
+    /// Like `Jump`, but only if the value on top of the stack is falsey. Leaves it there.
+    JumpIfFalse(u16),
+    /// Jumps back by the given number of instructions.
+    Loop(u16),
+    Call(u8),
+    /// Wraps a function constant in a closure, capturing the upvalues it lists.
+    Closure(u16),
+    /// Replaces the given number of values on top of the stack with a list of them.
+    BuildList(u16),
+    /// Replaces the given number of key and value pairs on top of the stack with a map of
+    /// them.
+    BuildMap(u16),
+    /// Replaces a list or map and an index with the element at that index.
+    Index,
+    /// Stores the value on top of the stack into a list or map at an index, leaving the value.
+    SetIndex,
+    /// Moves the local on top of the stack into the heap before popping it, for closures
+    /// that captured it.
+    CloseUpvalue,
+    Return,
+    /// Replaces the given number of method closures on top of the stack with a class of
+    /// them, named by the constant.
+    Class(u16, u8),
+    /// Like `Class`, but the class inherits from the value below the methods, which stays.
+    Subclass(u16, u8),
+    /// Replaces an instance with its property named by the constant.
+    GetProperty(u16),
+    /// Stores the value on top of the stack into the field of the instance below it named by
+    /// the constant, leaving the value.
+    SetProperty(u16),
+    /// Replaces `this` and the superclass on top of the stack with the superclass's method
+    /// named by the constant, bound to `this`.
+    GetSuper(u16),
+    /// Stops with a runtime error whose message is the constant, for an error the compiler
+    /// already knows the code will run into.
+    Fail(u16),
+    /// Throws the value on top of the stack.
+    Throw,
+    /// Starts code that a `catch` clause handles. An error in it unwinds the stack and jumps
+    /// forward by the given number of instructions, with the exception on top of the stack.
+    Catch(u16),
+    /// Starts code that a `finally` block covers. An error in it unwinds the stack and jumps
+    /// forward by the given number of instructions, to a copy of the block ending in
+    /// `Rethrow`.
+    Finally(u16),
+    /// Ends the innermost code started by `Catch` or `Finally`.
+    EndTry,
+    /// Raises the error that sent the innermost `Finally` to its block again.
+    Rethrow,
+}
+
+/// Compile-time constants. Functions live here until `OpCode::Closure` turns them into values.
+#[derive(Debug)]
+pub enum Constant {
+    Value(Value),
+    Function(Rc<Function>),
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Value(value) => write!(f, "{:?}", value),
+            Constant::Function(function) => write!(f, "{}", function),
+        }
+    }
+}
+
+/// A compiled block of code with its constants and the source token of each instruction.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub constants: Vec<Constant>,
+    // Run-length encoded as (first instruction, token) pairs, since long stretches of code
+    // come from the same token. Runtime errors point at the token, line and span both.
+    tokens: Vec<(usize, Token)>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, op: OpCode, token: &Token) -> usize {
+        let same_token = self
+            .tokens
+            .last()
+            .is_some_and(|(_, last)| last == token && last.span == token.span);
+        if !same_token {
+            self.tokens.push((self.code.len(), token.clone()));
+        }
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    /// Adds `constant` to the pool, returning its index, or `None` if the pool is full.
+    pub fn add_constant(&mut self, constant: Constant) -> Option<u16> {
+        let index = u16::try_from(self.constants.len()).ok()?;
+        self.constants.push(constant);
+        Some(index)
+    }
+
+    /// Lists every instruction with its offset, line and operands, clox style.
+    pub fn disassemble(&self, name: &str) -> String {
+        let mut out = format!("== {} ==\n", name);
+        for offset in 0..self.code.len() {
+            out.push_str(&self.disassemble_instruction(offset));
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn disassemble_instruction(&self, offset: usize) -> String {
+        let line = if offset > 0 && self.line(offset) == self.line(offset - 1) {
+            "   |".to_string()
+        } else {
+            format!("{:4}", self.line(offset))
+        };
+        let prefix = format!("{:04} {} ", offset, line);
+
+        let op = self.code[offset];
+        let operand = match op {
+            OpCode::Constant(index)
+            | OpCode::GetGlobal(index)
+            | OpCode::DefineGlobal(index)
+            | OpCode::SetGlobal(index)
+            | OpCode::GetProperty(index)
+            | OpCode::SetProperty(index)
+            | OpCode::GetSuper(index)
+            | OpCode::Fail(index) => {
+                Some(format!("{:4} {}", index, self.constants[index as usize]))
+            }
+            OpCode::GetLocal(index)
+            | OpCode::SetLocal(index)
+            | OpCode::GetUpvalue(index)
+            | OpCode::SetUpvalue(index) => Some(format!("{:4}", index)),
+            OpCode::Call(count) => Some(format!("{:4}", count)),
+            OpCode::BuildList(count) | OpCode::BuildMap(count) => Some(format!("{:4}", count)),
+            OpCode::Class(index, count) | OpCode::Subclass(index, count) => Some(format!(
+                "{:4} {} {}",
+                index, self.constants[index as usize], count
+            )),
+            OpCode::Jump(distance)
+            | OpCode::JumpIfFalse(distance)
+            | OpCode::Catch(distance)
+            | OpCode::Finally(distance) => Some(format!(
+                "{:4} -> {:04}",
+                distance,
+                offset + 1 + distance as usize
+            )),
+            OpCode::Loop(distance) => Some(format!(
+                "{:4} -> {:04}",
+                distance,
+                offset + 1 - distance as usize
+            )),
+            OpCode::Closure(index) => {
+                let mut operand = format!("{:4} {}", index, self.constants[index as usize]);
+                if let Constant::Function(function) = &self.constants[index as usize] {
+                    for upvalue in &function.upvalues {
+                        let kind = if upvalue.is_local { "local" } else { "upvalue" };
+                        operand.push_str(&format!(
+                            "\n{:04}    |   {:<16} {} {}",
+                            offset, "", kind, upvalue.index
+                        ));
+                    }
+                }
+                Some(operand)
+            }
+            _ => None,
+        };
+
+        // `{:?}` prints the operand in parentheses after the name.
+        let name = format!("{:?}", op);
+        let name = name.split('(').next().unwrap_or_default();
+        match operand {
+            Some(operand) => format!("{}{:<16} {}", prefix, name, operand),
+            None => format!("{}{}", prefix, name),
+        }
+    }
+
+    /// The token the instruction at `offset` was compiled from.
+    pub fn token(&self, offset: usize) -> Option<&Token> {
+        let run = self.tokens.partition_point(|(start, _)| *start <= offset);
+        run.checked_sub(1).map(|run| &self.tokens[run].1)
+    }
+
+    /// The source line the instruction at `offset` was compiled from.
+    pub fn line(&self, offset: usize) -> usize {
+        self.token(offset).map_or(0, |token| token.line)
+    }
+}
+
+/// How a closure finds one of its upvalues when it is created: either a local slot of the
+/// function enclosing it, or one of that function's own upvalues.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UpvalueRef {
+    pub is_local: bool,
+    pub index: u16,
+}
+
+#[derive(Debug, Default)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub chunk: Chunk,
+    pub upvalues: Vec<UpvalueRef>,
+}
+
+impl Function {
+    /// Disassembles this function followed by every function nested inside it.
+    pub fn disassemble(&self) -> String {
+        let mut out = self.chunk.disassemble(&self.to_string());
+        for constant in &self.chunk.constants {
+            if let Constant::Function(function) = constant {
+                out.push('\n');
+                out.push_str(&function.disassemble());
+            }
+        }
+        out
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "<script>")
+        } else {
+            write!(f, "<fn {}>", self.name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::TokenType::Identifier;
+
+    fn token(line: usize) -> Token {
+        Token::new(Identifier, "x", None, line)
+    }
+
+    #[test]
+    fn test_line_table() {
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::Nil, &token(1));
+        chunk.write(OpCode::Nil, &token(1));
+        chunk.write(OpCode::Print, &token(3));
+        chunk.write(OpCode::Return, &token(4));
+
+        assert_eq!(chunk.tokens.len(), 3);
+        assert_eq!(
+            (0..4).map(|offset| chunk.line(offset)).collect::<Vec<_>>(),
+            vec![1, 1, 3, 4]
+        );
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let mut chunk = Chunk::new();
+        let constant = chunk
+            .add_constant(Constant::Value(Value::String_("hi".into())))
+            .unwrap();
+        chunk.write(OpCode::Constant(constant), &token(1));
+        chunk.write(OpCode::JumpIfFalse(1), &token(1));
+        chunk.write(OpCode::Print, &token(2));
+        chunk.write(OpCode::Loop(3), &token(2));
+
+        assert_eq!(
+            chunk.disassemble("test"),
+            "== test ==
+0000    1 Constant            0 \"hi\"
+0001    | JumpIfFalse         1 -> 0003
+0002    2 Print
+0003    | Loop                3 -> 0001
+"
+        );
+    }
+}
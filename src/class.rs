@@ -0,0 +1,171 @@
+use crate::ast::Value;
+use crate::error::RoxError;
+use crate::function::RoxFunction;
+use crate::gc::{self, Heap, Trace};
+use crate::intern::intern;
+use crate::native::Arity;
+use crate::vm::Closure;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A method as its class holds it: a function for the interpreter, or a closure for the VM.
+#[derive(Clone)]
+pub enum Method {
+    Function(RoxFunction),
+    Closure(Rc<Closure>),
+}
+
+impl Trace for Method {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        match self {
+            Method::Function(function) => function.trace(visit),
+            Method::Closure(closure) => visit(gc::address(closure)),
+        }
+    }
+}
+
+impl Method {
+    pub fn arity(&self) -> Arity {
+        match self {
+            Method::Function(function) => function.arity(),
+            Method::Closure(closure) => Arity::Fixed(closure.function.arity),
+        }
+    }
+
+    /// The method with `this` bound to `instance`, as a value that can be called.
+    pub fn bind(&self, instance: Value, heap: &mut Heap) -> Value {
+        match self {
+            Method::Function(function) => Value::Callable(function.bind(instance, heap)),
+            Method::Closure(closure) => Value::Closure(heap.closure(Closure {
+                function: Rc::clone(&closure.function),
+                upvalues: closure.upvalues.clone(),
+                receiver: Some(instance),
+            })),
+        }
+    }
+}
+
+pub struct RoxClass {
+    pub name: String,
+    pub superclass: Option<Rc<RoxClass>>,
+    methods: HashMap<String, Method>,
+}
+
+impl Trace for RoxClass {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        if let Some(superclass) = &self.superclass {
+            visit(gc::address(superclass));
+        }
+        for method in self.methods.values() {
+            method.trace(visit);
+        }
+    }
+}
+
+impl RoxClass {
+    pub fn new(
+        name: String,
+        superclass: Option<Rc<RoxClass>>,
+        methods: HashMap<String, Method>,
+    ) -> Self {
+        Self {
+            name,
+            superclass,
+            methods,
+        }
+    }
+
+    /// Finds a method on this class, walking up the superclass chain if needed.
+    pub fn find_method(&self, name: &str) -> Option<Method> {
+        match self.methods.get(name) {
+            Some(method) => Some(method.clone()),
+            None => self
+                .superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find_method(name)),
+        }
+    }
+}
+
+pub struct RoxInstance {
+    pub class: Rc<RoxClass>,
+    fields: HashMap<Rc<str>, Value>,
+}
+
+impl Trace for RoxInstance {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        visit(gc::address(&self.class));
+        for value in self.fields.values() {
+            value.trace(visit);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.fields.clear();
+    }
+}
+
+impl RoxInstance {
+    pub fn new(class: Rc<RoxClass>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+
+    /// Looks up a field, falling back to a method of the instance's class bound to `this`.
+    pub fn get(instance: &Rc<RefCell<RoxInstance>>, name: &str, heap: &mut Heap) -> Option<Value> {
+        if let Some(value) = instance.borrow().fields.get(name) {
+            return Some(value.clone());
+        }
+
+        let method = instance.borrow().class.find_method(name)?;
+        Some(method.bind(Value::Instance(Rc::clone(instance)), heap))
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.fields.insert(intern(name), value);
+    }
+}
+
+/// Turns an error into the value a `catch` clause receives: the thrown value itself, or for a
+/// runtime error an instance of `error_class` with its `message` and `line`. Errors that
+/// aren't exceptions are handed back.
+pub fn exception_value(
+    err: RoxError,
+    error_class: &Rc<RoxClass>,
+    heap: &mut Heap,
+) -> Result<Value, RoxError> {
+    let (message, line) = match err {
+        RoxError::Throw(value, ..) => return Ok(*value),
+        RoxError::RuntimeError(token, message, _) => (message, token.line),
+        RoxError::UndefinedVariableError(ref token, _) => (err.message(), token.line),
+        err => return Err(err),
+    };
+
+    let mut error = RoxInstance::new(Rc::clone(error_class));
+    error.set("message", message.into());
+    error.set("line", Value::Number(line as f64));
+    Ok(Value::Instance(heap.instance(error)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instance_fields() {
+        let class = Rc::new(RoxClass::new("Point".to_string(), None, HashMap::new()));
+        let instance = Rc::new(RefCell::new(RoxInstance::new(class)));
+        let mut heap = Heap::new();
+
+        assert!(RoxInstance::get(&instance, "x", &mut heap).is_none());
+
+        instance.borrow_mut().set("x", Value::Number(1.0));
+        assert_eq!(
+            RoxInstance::get(&instance, "x", &mut heap),
+            Some(Value::Number(1.0))
+        );
+    }
+}
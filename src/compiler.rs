@@ -0,0 +1,1023 @@
+use crate::ast::{Expr, Stmt, Value};
+use crate::chunk::{Constant, Function, OpCode, UpvalueRef};
+use crate::error::RoxError;
+use crate::intern::intern;
+use crate::token::TokenType::{
+    BangEqual, Eof, EqualEqual, Greater, GreaterEqual, Identifier, Less, LessEqual, Minus, Or,
+    Plus, Slash, Star, Super, This,
+};
+use crate::token::{Literal, Token};
+use std::rc::Rc;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    // Set once a closure captures it, so leaving its scope must hoist it off the stack.
+    is_captured: bool,
+}
+
+/// A loop being compiled, for the jumps its `break` and `continue` statements leave behind.
+struct Loop {
+    // The scope depth outside the loop: locals deeper than this go when jumping out of it.
+    scope_depth: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// A `try` statement being compiled, for the jumps that leave it early.
+struct Try {
+    // How many loops it is inside of, so `break` and `continue` know whether they leave it.
+    loop_depth: usize,
+    // How many locals were declared outside it.
+    local_count: usize,
+    // How many VM handlers it has at this point, each of which a jump out has to end.
+    handlers: usize,
+    // Run by every jump out, except one from the block itself.
+    finally: Option<Vec<Stmt>>,
+}
+
+/// Per-function state. The compiler keeps a stack of these, one for each function being
+/// compiled, innermost last.
+struct FunctionCompiler {
+    function: Function,
+    function_type: FunctionType,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+    tries: Vec<Try>,
+}
+
+impl FunctionCompiler {
+    fn new(name: String, function_type: FunctionType) -> Self {
+        // Slot zero holds the function being called, or for a method the instance.
+        let slot_zero = match function_type {
+            FunctionType::Method | FunctionType::Initializer => "this",
+            FunctionType::Script | FunctionType::Function => "",
+        };
+        Self {
+            function: Function {
+                name,
+                ..Function::default()
+            },
+            function_type,
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: 0,
+                is_captured: false,
+            }],
+            scope_depth: 0,
+            loops: Vec::new(),
+            tries: Vec::new(),
+        }
+    }
+}
+
+/// Lowers a resolved syntax tree to bytecode for the `vm`. Locals live in stack slots and
+/// captured variables become upvalues, so nothing is looked up by name except globals.
+pub struct Compiler {
+    functions: Vec<FunctionCompiler>,
+    // The last token compiled, for line numbers and error locations.
+    token: Token,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            functions: Vec::new(),
+            token: Token::new(Eof, "", None, 1),
+        }
+    }
+
+    /// Compiles a script into the function the VM runs at the top level.
+    pub fn compile(self, statements: &[Stmt]) -> Result<Rc<Function>, RoxError> {
+        self.script(|compiler| {
+            for statement in statements {
+                compiler.statement(statement)?;
+            }
+            compiler.emit_return();
+            Ok(())
+        })
+    }
+
+    /// Compiles a lone expression into a script that returns its value.
+    pub fn compile_expression(self, expr: &Expr) -> Result<Rc<Function>, RoxError> {
+        self.script(|compiler| {
+            compiler.expression(expr)?;
+            compiler.emit(OpCode::Return);
+            Ok(())
+        })
+    }
+
+    fn script(
+        mut self,
+        body: impl FnOnce(&mut Self) -> Result<(), RoxError>,
+    ) -> Result<Rc<Function>, RoxError> {
+        self.functions
+            .push(FunctionCompiler::new(String::new(), FunctionType::Script));
+        body(&mut self)?;
+
+        let compiler = self
+            .functions
+            .pop()
+            .expect("The script is always being compiled.");
+        Ok(Rc::new(compiler.function))
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), RoxError> {
+        match stmt {
+            Stmt::Print(keyword, expr) => {
+                self.expression(expr)?;
+                self.token = keyword.clone();
+                self.emit(OpCode::Print);
+            }
+            Stmt::Expression(expr) => {
+                self.expression(expr)?;
+                self.emit(OpCode::Pop);
+            }
+            Stmt::Block(statements) => self.block(statements)?,
+            Stmt::Var(name, initializer) => {
+                self.token = name.clone();
+                match initializer {
+                    Some(initializer) => self.expression(initializer)?,
+                    None => {
+                        self.emit(OpCode::Nil);
+                    }
+                }
+                self.define_variable(name)?;
+            }
+            Stmt::If(condition, then_branch, else_branch) => {
+                self.expression(condition)?;
+                let then_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+                self.statement(then_branch)?;
+
+                let else_jump = self.emit(OpCode::Jump(0));
+                self.patch_jump(then_jump)?;
+                self.emit(OpCode::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch)?;
+                }
+                self.patch_jump(else_jump)?;
+            }
+            Stmt::While(condition, body, increment) => {
+                let loop_start = self.current().function.chunk.code.len();
+                self.expression(condition)?;
+                let exit_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+
+                let scope_depth = self.current().scope_depth;
+                self.current_mut().loops.push(Loop {
+                    scope_depth,
+                    breaks: Vec::new(),
+                    continues: Vec::new(),
+                });
+                self.statement(body)?;
+                let Loop {
+                    breaks, continues, ..
+                } = self
+                    .current_mut()
+                    .loops
+                    .pop()
+                    .expect("The loop was pushed.");
+
+                for jump in continues {
+                    self.patch_jump(jump)?;
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment)?;
+                    self.emit(OpCode::Pop);
+                }
+                self.emit_loop(loop_start)?;
+
+                self.patch_jump(exit_jump)?;
+                self.emit(OpCode::Pop);
+                for jump in breaks {
+                    self.patch_jump(jump)?;
+                }
+            }
+            Stmt::Break(keyword) | Stmt::Continue(keyword) => {
+                self.token = keyword.clone();
+                let loop_depth = self.current().loops.len();
+                let inside_loop = self
+                    .current()
+                    .tries
+                    .iter()
+                    .rev()
+                    .take_while(|try_| try_.loop_depth == loop_depth)
+                    .count();
+                self.exit_tries(inside_loop)?;
+
+                self.token = keyword.clone();
+                self.discard_loop_locals();
+                let jump = self.emit(OpCode::Jump(0));
+                let innermost = self
+                    .current_mut()
+                    .loops
+                    .last_mut()
+                    .expect("The parser rejects 'break' and 'continue' outside of a loop.");
+                match stmt {
+                    Stmt::Break(_) => innermost.breaks.push(jump),
+                    _ => innermost.continues.push(jump),
+                }
+            }
+            Stmt::Function(name, params, body) => {
+                // A local function is in scope inside its own body, so it can recurse.
+                if self.current().scope_depth > 0 {
+                    self.add_local(name)?;
+                }
+                self.function(name, params, body, FunctionType::Function)?;
+                if self.current().scope_depth == 0 {
+                    let global = self.identifier_constant(name)?;
+                    self.emit(OpCode::DefineGlobal(global));
+                }
+            }
+            Stmt::Class(name, superclass, methods) => {
+                self.class(name, superclass.as_ref(), methods)?
+            }
+            Stmt::Throw(keyword, value) => {
+                self.expression(value)?;
+                self.token = keyword.clone();
+                self.emit(OpCode::Throw);
+            }
+            Stmt::Try(keyword, body, catch, finally) => {
+                self.try_statement(keyword, body, catch.as_ref(), finally.as_ref())?
+            }
+            Stmt::Return(keyword, value) => {
+                self.token = keyword.clone();
+                match value {
+                    Some(value) => self.expression(value)?,
+                    None => self.emit_default_return(),
+                }
+
+                let tries = self.current().tries.len();
+                if tries > 0 {
+                    // Keeps the value out of the way of any `finally` block's locals.
+                    self.add_local(&Token::new(Identifier, "", None, keyword.line))?;
+                    self.exit_tries(tries)?;
+                    self.current_mut().locals.pop();
+                    self.token = keyword.clone();
+                }
+                self.emit(OpCode::Return);
+            }
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<(), RoxError> {
+        match expr {
+            Expr::Literal(literal) => {
+                match literal {
+                    Literal::Nil => self.emit(OpCode::Nil),
+                    Literal::Bool(true) => self.emit(OpCode::True),
+                    Literal::Bool(false) => self.emit(OpCode::False),
+                    _ => {
+                        let constant =
+                            self.make_constant(Constant::Value(literal.clone().into()))?;
+                        self.emit(OpCode::Constant(constant))
+                    }
+                };
+            }
+            Expr::Unary(operator, right) => {
+                self.expression(right)?;
+                self.token = operator.clone();
+                match operator.token_type {
+                    Minus => self.emit(OpCode::Negate),
+                    _ => self.emit(OpCode::Not),
+                };
+            }
+            Expr::Binary(left, operator, right) => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.token = operator.clone();
+                match operator.token_type {
+                    Plus => self.emit(OpCode::Add),
+                    Minus => self.emit(OpCode::Subtract),
+                    Star => self.emit(OpCode::Multiply),
+                    Slash => self.emit(OpCode::Divide),
+                    Greater => self.emit(OpCode::Greater),
+                    GreaterEqual => self.emit(OpCode::GreaterEqual),
+                    Less => self.emit(OpCode::Less),
+                    LessEqual => self.emit(OpCode::LessEqual),
+                    EqualEqual => self.emit(OpCode::Equal),
+                    BangEqual => {
+                        self.emit(OpCode::Equal);
+                        self.emit(OpCode::Not)
+                    }
+                    _ => return Err(self.error(operator, "Unknown binary operator.")),
+                };
+            }
+            Expr::Grouping(expr) => self.expression(expr)?,
+            Expr::Var(name, _) => {
+                let op = self.variable_op(name, false)?;
+                self.emit(op);
+            }
+            Expr::Assign(name, value, _) => {
+                self.expression(value)?;
+                let op = self.variable_op(name, true)?;
+                self.emit(op);
+            }
+            Expr::Logical(left, operator, right) => {
+                self.expression(left)?;
+                self.token = operator.clone();
+                if operator.token_type == Or {
+                    // Skip the right operand when the left one is truthy.
+                    let else_jump = self.emit(OpCode::JumpIfFalse(0));
+                    let end_jump = self.emit(OpCode::Jump(0));
+                    self.patch_jump(else_jump)?;
+                    self.emit(OpCode::Pop);
+                    self.expression(right)?;
+                    self.patch_jump(end_jump)?;
+                } else {
+                    let end_jump = self.emit(OpCode::JumpIfFalse(0));
+                    self.emit(OpCode::Pop);
+                    self.expression(right)?;
+                    self.patch_jump(end_jump)?;
+                }
+            }
+            Expr::Call(callee, paren, args) => {
+                self.expression(callee)?;
+                for arg in args {
+                    self.expression(arg)?;
+                }
+                self.token = paren.clone();
+                let count = u8::try_from(args.len())
+                    .map_err(|_| self.error(paren, "Can't have more than 255 arguments."))?;
+                self.emit(OpCode::Call(count));
+            }
+            Expr::List(bracket, elements) => {
+                for element in elements {
+                    self.expression(element)?;
+                }
+                self.token = bracket.clone();
+                let count = u16::try_from(elements.len())
+                    .map_err(|_| self.error(bracket, "Too many elements in a list literal."))?;
+                self.emit(OpCode::BuildList(count));
+            }
+            Expr::Lambda(keyword, params, body) => {
+                let name = Token::new(Identifier, "lambda", None, keyword.line);
+                self.function(&name, params, body, FunctionType::Function)?;
+            }
+            Expr::Map(brace, entries) => {
+                for (key, value) in entries {
+                    self.expression(key)?;
+                    self.expression(value)?;
+                }
+                self.token = brace.clone();
+                let count = u16::try_from(entries.len())
+                    .map_err(|_| self.error(brace, "Too many entries in a map literal."))?;
+                self.emit(OpCode::BuildMap(count));
+            }
+            Expr::Index(object, bracket, index) => {
+                self.expression(object)?;
+                self.expression(index)?;
+                self.token = bracket.clone();
+                self.emit(OpCode::Index);
+            }
+            Expr::SetIndex(object, bracket, index, value) => {
+                self.expression(object)?;
+                self.expression(index)?;
+                self.expression(value)?;
+                self.token = bracket.clone();
+                self.emit(OpCode::SetIndex);
+            }
+            Expr::Get(object, name) => {
+                self.expression(object)?;
+                let name_constant = self.identifier_constant(name)?;
+                self.token = name.clone();
+                self.emit(OpCode::GetProperty(name_constant));
+            }
+            Expr::Set(object, name, value) => {
+                self.expression(object)?;
+                self.expression(value)?;
+                let name_constant = self.identifier_constant(name)?;
+                self.token = name.clone();
+                self.emit(OpCode::SetProperty(name_constant));
+            }
+            Expr::This(keyword, _) => {
+                let op = self.variable_op(keyword, false)?;
+                self.emit(op);
+            }
+            Expr::Super(keyword, method, _) => {
+                let this = Token::new(This, "this", None, keyword.line);
+                let op = self.variable_op(&this, false)?;
+                self.emit(op);
+                let op = self.variable_op(keyword, false)?;
+                self.emit(op);
+
+                let name_constant = self.identifier_constant(method)?;
+                self.token = method.clone();
+                self.emit(OpCode::GetSuper(name_constant));
+            }
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, statements: &[Stmt]) -> Result<(), RoxError> {
+        self.begin_scope();
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    fn class(
+        &mut self,
+        name: &Token,
+        superclass: Option<&Expr>,
+        methods: &[Stmt],
+    ) -> Result<(), RoxError> {
+        self.token = name.clone();
+        let name_constant = self.identifier_constant(name)?;
+        // Like a local function, a local class is in scope inside its own methods. Its slot
+        // is filled in once the class exists.
+        let slot = if self.current().scope_depth > 0 {
+            self.emit(OpCode::Nil);
+            self.add_local(name)?;
+            Some((self.current().locals.len() - 1) as u16)
+        } else {
+            None
+        };
+
+        let superclass_name = match superclass {
+            Some(Expr::Var(superclass_name, _)) => superclass_name.clone(),
+            _ => name.clone(),
+        };
+        if let Some(superclass) = superclass {
+            if superclass_name.lexeme == name.lexeme {
+                // The interpreter only finds out once it runs the declaration, so wait as well.
+                self.token = superclass_name;
+                let message = self
+                    .make_constant(Constant::Value("A class can't inherit from itself.".into()))?;
+                self.emit(OpCode::Fail(message));
+                return Ok(());
+            }
+
+            // Methods of a subclass close over a scope in which `super` is the superclass.
+            self.begin_scope();
+            self.expression(superclass)?;
+            self.add_local(&Token::new(Super, "super", None, superclass_name.line))?;
+        }
+
+        for method in methods {
+            if let Stmt::Function(method_name, params, body) = method {
+                let function_type = if method_name.lexeme == "init" {
+                    FunctionType::Initializer
+                } else {
+                    FunctionType::Method
+                };
+                self.function(method_name, params, body, function_type)?;
+            }
+        }
+        let count = u8::try_from(methods.len())
+            .map_err(|_| self.error(name, "Can't have more than 255 methods in a class."))?;
+
+        if superclass.is_some() {
+            self.token = superclass_name;
+            self.emit(OpCode::Subclass(name_constant, count));
+        } else {
+            self.token = name.clone();
+            self.emit(OpCode::Class(name_constant, count));
+        }
+        match slot {
+            Some(slot) => {
+                self.emit(OpCode::SetLocal(slot));
+                self.emit(OpCode::Pop);
+            }
+            None => {
+                self.emit(OpCode::DefineGlobal(name_constant));
+            }
+        }
+
+        if superclass.is_some() {
+            self.end_scope();
+        }
+        Ok(())
+    }
+
+    /// Lowers `try` to a `Finally` handler around a `Catch` handler around the body. The
+    /// `finally` block is compiled twice: once for when the rest finishes normally and once
+    /// for when an error sends the VM to it, ending with `Rethrow`. Jumps out of the statement
+    /// get copies of their own from `exit_tries`.
+    fn try_statement(
+        &mut self,
+        keyword: &Token,
+        body: &[Stmt],
+        catch: Option<&(Token, Vec<Stmt>)>,
+        finally: Option<&Vec<Stmt>>,
+    ) -> Result<(), RoxError> {
+        self.token = keyword.clone();
+        let loop_depth = self.current().loops.len();
+        let local_count = self.current().locals.len();
+        let finally_handler = finally.map(|_| self.emit(OpCode::Finally(0)));
+        self.current_mut().tries.push(Try {
+            loop_depth,
+            local_count,
+            handlers: usize::from(finally.is_some()),
+            finally: finally.cloned(),
+        });
+
+        match catch {
+            Some((name, handler)) => {
+                let catch_handler = self.emit(OpCode::Catch(0));
+                self.innermost_try().handlers += 1;
+                self.block(body)?;
+                self.token = keyword.clone();
+                self.emit(OpCode::EndTry);
+                self.innermost_try().handlers -= 1;
+                let end_jump = self.emit(OpCode::Jump(0));
+
+                // The VM has ended the handler and left the exception in the variable's slot.
+                self.patch_jump(catch_handler)?;
+                self.begin_scope();
+                self.add_local(name)?;
+                for statement in handler {
+                    self.statement(statement)?;
+                }
+                self.end_scope();
+                self.patch_jump(end_jump)?;
+            }
+            None => self.block(body)?,
+        }
+        self.current_mut().tries.pop();
+
+        if let (Some(finally_handler), Some(finally)) = (finally_handler, finally) {
+            self.token = keyword.clone();
+            self.emit(OpCode::EndTry);
+            self.block(finally)?;
+            let end_jump = self.emit(OpCode::Jump(0));
+
+            // The handler stays until `Rethrow`, so a jump out of the block has to end it.
+            self.patch_jump(finally_handler)?;
+            self.current_mut().tries.push(Try {
+                loop_depth,
+                local_count,
+                handlers: 1,
+                finally: None,
+            });
+            self.block(finally)?;
+            self.current_mut().tries.pop();
+            self.token = keyword.clone();
+            self.emit(OpCode::Rethrow);
+            self.patch_jump(end_jump)?;
+        }
+        Ok(())
+    }
+
+    /// Leaves the innermost `count` `try` statements ahead of a jump out of them: ends their
+    /// handlers and runs their `finally` blocks, innermost first.
+    fn exit_tries(&mut self, count: usize) -> Result<(), RoxError> {
+        let tries = self.current().tries.len();
+        for index in (tries - count..tries).rev() {
+            let try_ = &self.current().tries[index];
+            let (loop_depth, local_count, handlers) =
+                (try_.loop_depth, try_.local_count, try_.handlers);
+            let finally = try_.finally.clone();
+            for _ in 0..handlers {
+                self.emit(OpCode::EndTry);
+            }
+
+            if let Some(finally) = finally {
+                // Compile the block as it stands after the statement: the variables declared
+                // inside it are hidden, and jumps in the block only leave the code around it.
+                let current = self.current_mut();
+                let hidden: Vec<String> = current.locals[local_count..]
+                    .iter_mut()
+                    .map(|local| std::mem::take(&mut local.name))
+                    .collect();
+                let inner_tries = current.tries.split_off(index);
+                let inner_loops = current.loops.split_off(loop_depth);
+
+                self.block(&finally)?;
+
+                let current = self.current_mut();
+                current.tries.extend(inner_tries);
+                current.loops.extend(inner_loops);
+                for (local, name) in current.locals[local_count..].iter_mut().zip(hidden) {
+                    local.name = name;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn function(
+        &mut self,
+        name: &Token,
+        params: &[Token],
+        body: &[Stmt],
+        function_type: FunctionType,
+    ) -> Result<(), RoxError> {
+        self.functions
+            .push(FunctionCompiler::new(name.lexeme.clone(), function_type));
+        self.begin_scope();
+        for param in params {
+            self.current_mut().function.arity += 1;
+            self.add_local(param)?;
+        }
+        for statement in body {
+            self.statement(statement)?;
+        }
+        self.emit_return();
+
+        // No need to end the scope: returning discards the whole frame.
+        let compiler = self.functions.pop().expect("A function is being compiled.");
+        self.token = name.clone();
+        let constant = self.make_constant(Constant::Function(Rc::new(compiler.function)))?;
+        self.emit(OpCode::Closure(constant));
+        Ok(())
+    }
+
+    /// Declares the variable whose initial value is on top of the stack.
+    fn define_variable(&mut self, name: &Token) -> Result<(), RoxError> {
+        if self.current().scope_depth > 0 {
+            // The value is already sitting in the new local's slot.
+            return self.add_local(name);
+        }
+
+        let global = self.identifier_constant(name)?;
+        self.emit(OpCode::DefineGlobal(global));
+        Ok(())
+    }
+
+    /// The instruction that reads (or, with `assign`, writes) the variable `name`.
+    fn variable_op(&mut self, name: &Token, assign: bool) -> Result<OpCode, RoxError> {
+        self.token = name.clone();
+        let innermost = self.functions.len() - 1;
+
+        let op = if let Some(slot) = self.resolve_local(innermost, name) {
+            if assign {
+                OpCode::SetLocal(slot)
+            } else {
+                OpCode::GetLocal(slot)
+            }
+        } else if let Some(index) = self.resolve_upvalue(innermost, name)? {
+            if assign {
+                OpCode::SetUpvalue(index)
+            } else {
+                OpCode::GetUpvalue(index)
+            }
+        } else {
+            let global = self.identifier_constant(name)?;
+            if assign {
+                OpCode::SetGlobal(global)
+            } else {
+                OpCode::GetGlobal(global)
+            }
+        };
+        Ok(op)
+    }
+
+    fn resolve_local(&self, function: usize, name: &Token) -> Option<u16> {
+        self.functions[function]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name.lexeme)
+            .map(|slot| slot as u16)
+    }
+
+    /// Finds `name` in the functions enclosing `function`, threading it through each of
+    /// them as an upvalue on the way back in.
+    fn resolve_upvalue(&mut self, function: usize, name: &Token) -> Result<Option<u16>, RoxError> {
+        if function == 0 {
+            return Ok(None);
+        }
+
+        if let Some(slot) = self.resolve_local(function - 1, name) {
+            self.functions[function - 1].locals[slot as usize].is_captured = true;
+            return self.add_upvalue(function, name, true, slot).map(Some);
+        }
+
+        match self.resolve_upvalue(function - 1, name)? {
+            Some(index) => self.add_upvalue(function, name, false, index).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(
+        &mut self,
+        function: usize,
+        name: &Token,
+        is_local: bool,
+        index: u16,
+    ) -> Result<u16, RoxError> {
+        let upvalue = UpvalueRef { is_local, index };
+        let upvalues = &mut self.functions[function].function.upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == upvalue) {
+            return Ok(existing as u16);
+        }
+
+        let index = u16::try_from(upvalues.len())
+            .map_err(|_| self.error(name, "Too many closure variables in function."))?;
+        self.functions[function].function.upvalues.push(upvalue);
+        Ok(index)
+    }
+
+    fn add_local(&mut self, name: &Token) -> Result<(), RoxError> {
+        if self.current().locals.len() > u16::MAX as usize {
+            return Err(self.error(name, "Too many local variables in function."));
+        }
+
+        let depth = self.current().scope_depth;
+        self.current_mut().locals.push(Local {
+            name: name.lexeme.clone(),
+            depth,
+            is_captured: false,
+        });
+        Ok(())
+    }
+
+    fn begin_scope(&mut self) {
+        self.current_mut().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.current_mut().scope_depth -= 1;
+
+        let depth = self.current().scope_depth;
+        while let Some(local) = self.current().locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+
+            let op = if local.is_captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            };
+            self.emit(op);
+            self.current_mut().locals.pop();
+        }
+    }
+
+    /// Pops the locals declared inside the innermost loop, ahead of jumping out of its body.
+    /// The compiler keeps track of them, since the code after the jump still has them in scope.
+    fn discard_loop_locals(&mut self) {
+        let current = self.current();
+        let depth = current
+            .loops
+            .last()
+            .map_or(0, |innermost| innermost.scope_depth);
+        let ops: Vec<OpCode> = current
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| {
+                if local.is_captured {
+                    OpCode::CloseUpvalue
+                } else {
+                    OpCode::Pop
+                }
+            })
+            .collect();
+        for op in ops {
+            self.emit(op);
+        }
+    }
+
+    fn identifier_constant(&mut self, name: &Token) -> Result<u16, RoxError> {
+        self.make_constant(Constant::Value(Value::String_(intern(&name.lexeme))))
+    }
+
+    fn make_constant(&mut self, constant: Constant) -> Result<u16, RoxError> {
+        match self.current_mut().function.chunk.add_constant(constant) {
+            Some(index) => Ok(index),
+            None => Err(self.error(&self.token, "Too many constants in one chunk.")),
+        }
+    }
+
+    fn emit(&mut self, op: OpCode) -> usize {
+        let function = &mut self
+            .functions
+            .last_mut()
+            .expect("A function is being compiled.")
+            .function;
+        function.chunk.write(op, &self.token)
+    }
+
+    fn emit_return(&mut self) {
+        self.emit_default_return();
+        self.emit(OpCode::Return);
+    }
+
+    /// Pushes what a function returns without a value: `nil`, or for an initializer the
+    /// instance it initialized.
+    fn emit_default_return(&mut self) {
+        if self.current().function_type == FunctionType::Initializer {
+            self.emit(OpCode::GetLocal(0));
+        } else {
+            self.emit(OpCode::Nil);
+        }
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) -> Result<(), RoxError> {
+        // Jump back from just past the `Loop` instruction itself.
+        let distance = self.current().function.chunk.code.len() + 1 - loop_start;
+        let distance =
+            u16::try_from(distance).map_err(|_| self.error(&self.token, "Loop body too large."))?;
+        self.emit(OpCode::Loop(distance));
+        Ok(())
+    }
+
+    /// Points the jump at `offset` to the next instruction to be emitted.
+    fn patch_jump(&mut self, offset: usize) -> Result<(), RoxError> {
+        let distance = self.current().function.chunk.code.len() - offset - 1;
+        let distance = u16::try_from(distance)
+            .map_err(|_| self.error(&self.token, "Too much code to jump over."))?;
+
+        let code = &mut self.current_mut().function.chunk.code;
+        code[offset] = match code[offset] {
+            OpCode::Jump(_) => OpCode::Jump(distance),
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(distance),
+            OpCode::Catch(_) => OpCode::Catch(distance),
+            OpCode::Finally(_) => OpCode::Finally(distance),
+            op => unreachable!("{:?} is not a jump.", op),
+        };
+        Ok(())
+    }
+
+    fn current(&self) -> &FunctionCompiler {
+        self.functions
+            .last()
+            .expect("A function is being compiled.")
+    }
+
+    fn current_mut(&mut self) -> &mut FunctionCompiler {
+        self.functions
+            .last_mut()
+            .expect("A function is being compiled.")
+    }
+
+    fn innermost_try(&mut self) -> &mut Try {
+        self.current_mut()
+            .tries
+            .last_mut()
+            .expect("A try statement is being compiled.")
+    }
+
+    fn error(&self, token: &Token, message: &str) -> RoxError {
+        RoxError::CompileError(token.clone(), message.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn compile_source(source: &str) -> Result<Rc<Function>, RoxError> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let (statements, errors) = Parser::new(tokens).parse();
+        assert!(errors.is_empty());
+        Compiler::new().compile(&statements)
+    }
+
+    #[test]
+    fn test_compile_expression_statement() {
+        let script = compile_source("print 1 + 2 * 3;").unwrap();
+
+        assert_eq!(
+            script.chunk.code,
+            vec![
+                OpCode::Constant(0),
+                OpCode::Constant(1),
+                OpCode::Constant(2),
+                OpCode::Multiply,
+                OpCode::Add,
+                OpCode::Print,
+                OpCode::Nil,
+                OpCode::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_locals_and_upvalues() {
+        let script = compile_source("{ var a = 1; fun f() { return a; } print a; }").unwrap();
+
+        assert_eq!(
+            script.chunk.code,
+            vec![
+                OpCode::Constant(0),
+                OpCode::Closure(1),
+                OpCode::GetLocal(1),
+                OpCode::Print,
+                // `f` is dropped plainly; `a` was captured, so it is closed over.
+                OpCode::Pop,
+                OpCode::CloseUpvalue,
+                OpCode::Nil,
+                OpCode::Return,
+            ]
+        );
+
+        let f = match &script.chunk.constants[1] {
+            Constant::Function(f) => f,
+            _ => panic!("Expected a function constant."),
+        };
+        assert_eq!(
+            f.upvalues,
+            vec![UpvalueRef {
+                is_local: true,
+                index: 1
+            }]
+        );
+        assert_eq!(f.chunk.code[0], OpCode::GetUpvalue(0));
+    }
+
+    #[test]
+    fn test_compile_while_jumps() {
+        let script = compile_source("while (false) print 1;").unwrap();
+
+        assert_eq!(
+            script.chunk.code[..6],
+            [
+                OpCode::False,
+                OpCode::JumpIfFalse(4),
+                OpCode::Pop,
+                OpCode::Constant(0),
+                OpCode::Print,
+                OpCode::Loop(6),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_class() {
+        let script = compile_source("class A { init(x) { this.x = x; } }").unwrap();
+
+        assert_eq!(
+            script.chunk.code,
+            vec![
+                OpCode::Closure(1),
+                OpCode::Class(0, 1),
+                OpCode::DefineGlobal(0),
+                OpCode::Nil,
+                OpCode::Return,
+            ]
+        );
+
+        let init = match &script.chunk.constants[1] {
+            Constant::Function(init) => init,
+            _ => panic!("Expected a function constant."),
+        };
+        // `this` is slot zero, and an initializer hands it back.
+        assert_eq!(
+            init.chunk.code,
+            vec![
+                OpCode::GetLocal(0),
+                OpCode::GetLocal(1),
+                OpCode::SetProperty(0),
+                OpCode::Pop,
+                OpCode::GetLocal(0),
+                OpCode::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_try() {
+        let script =
+            compile_source("try { print 1; } catch (e) { print e; } finally { print 2; }").unwrap();
+
+        assert_eq!(
+            script.chunk.code,
+            vec![
+                OpCode::Finally(12),
+                OpCode::Catch(4),
+                OpCode::Constant(0),
+                OpCode::Print,
+                OpCode::EndTry,
+                OpCode::Jump(3),
+                OpCode::GetLocal(1),
+                OpCode::Print,
+                OpCode::Pop,
+                OpCode::EndTry,
+                OpCode::Constant(1),
+                OpCode::Print,
+                OpCode::Jump(3),
+                // Where an error sends the VM: the `finally` block again, then the error.
+                OpCode::Constant(2),
+                OpCode::Print,
+                OpCode::Rethrow,
+                OpCode::Nil,
+                OpCode::Return,
+            ]
+        );
+    }
+}
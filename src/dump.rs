@@ -0,0 +1,260 @@
+//! Readable listings of each pipeline stage, for `rox dump`.
+
+use crate::ast::{Expr, Stmt, Value};
+use crate::token::Token;
+
+/// How `ast` lays out the syntax tree.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AstFormat {
+    /// One node per line, children indented under their parent.
+    Tree,
+    /// One top-level statement per line, as nested S-expressions.
+    SExpr,
+}
+
+/// One line per token: its line and column, type and source text.
+pub fn tokens(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(|token| {
+            let position = format!("{}:{}", token.line, token.span.column);
+            let line = format!(
+                "{:<8} {:<13} {}",
+                position,
+                format!("{:?}", token.token_type),
+                token.lexeme
+            );
+            line.trim_end().to_string() + "\n"
+        })
+        .collect()
+}
+
+/// One line or subtree per statement, in the chosen `AstFormat`.
+pub fn ast(statements: &[Stmt], format: AstFormat) -> String {
+    statements
+        .iter()
+        .map(|stmt| {
+            let node = stmt_node(stmt);
+            match format {
+                AstFormat::Tree => node.tree(0),
+                AstFormat::SExpr => node.sexpr() + "\n",
+            }
+        })
+        .collect()
+}
+
+/// The shape shared by both formats: a label and the nodes under it.
+struct Node {
+    label: String,
+    children: Vec<Node>,
+    // Leaves print bare in S-expressions; other nodes keep their parentheses even when
+    // empty, as in `(return)`.
+    is_leaf: bool,
+}
+
+impl Node {
+    fn new(label: impl Into<String>, children: Vec<Node>) -> Self {
+        Self {
+            label: label.into(),
+            children,
+            is_leaf: false,
+        }
+    }
+
+    fn leaf(label: impl Into<String>) -> Self {
+        Self {
+            is_leaf: true,
+            ..Self::new(label, Vec::new())
+        }
+    }
+
+    fn tree(&self, depth: usize) -> String {
+        let mut out = format!("{}{}\n", "  ".repeat(depth), self.label);
+        for child in &self.children {
+            out.push_str(&child.tree(depth + 1));
+        }
+        out
+    }
+
+    fn sexpr(&self) -> String {
+        if self.is_leaf {
+            return self.label.clone();
+        }
+
+        let mut out = format!("({}", self.label);
+        for child in &self.children {
+            out.push(' ');
+            out.push_str(&child.sexpr());
+        }
+        out.push(')');
+        out
+    }
+}
+
+fn stmt_node(stmt: &Stmt) -> Node {
+    match stmt {
+        Stmt::Print(_, expr) => Node::new("print", vec![expr_node(expr)]),
+        Stmt::Expression(expr) => Node::new("expr", vec![expr_node(expr)]),
+        Stmt::Block(statements) => Node::new("block", statements.iter().map(stmt_node).collect()),
+        Stmt::Var(name, initializer) => Node::new(
+            format!("var {}", name.lexeme),
+            initializer.iter().map(expr_node).collect(),
+        ),
+        Stmt::If(condition, then_branch, else_branch) => {
+            let mut children = vec![expr_node(condition), stmt_node(then_branch)];
+            children.extend(else_branch.iter().map(|stmt| stmt_node(stmt)));
+            Node::new("if", children)
+        }
+        Stmt::While(condition, body, increment) => {
+            let mut children = vec![expr_node(condition), stmt_node(body)];
+            children.extend(increment.iter().map(expr_node));
+            Node::new("while", children)
+        }
+        Stmt::Break(_) => Node::leaf("break"),
+        Stmt::Continue(_) => Node::leaf("continue"),
+        Stmt::Throw(_, value) => Node::new("throw", vec![expr_node(value)]),
+        Stmt::Try(_, body, catch, finally) => {
+            let mut children = vec![Node::new("block", body.iter().map(stmt_node).collect())];
+            if let Some((name, handler)) = catch {
+                children.push(Node::new(
+                    format!("catch {}", name.lexeme),
+                    handler.iter().map(stmt_node).collect(),
+                ));
+            }
+            if let Some(finally) = finally {
+                children.push(Node::new(
+                    "finally",
+                    finally.iter().map(stmt_node).collect(),
+                ));
+            }
+            Node::new("try", children)
+        }
+        Stmt::Function(name, params, body) => function_node(&name.lexeme, params, body),
+        Stmt::Class(name, superclass, methods) => {
+            let label = match superclass {
+                Some(Expr::Var(superclass, _)) => {
+                    format!("class {} < {}", name.lexeme, superclass.lexeme)
+                }
+                _ => format!("class {}", name.lexeme),
+            };
+            Node::new(label, methods.iter().map(stmt_node).collect())
+        }
+        Stmt::Return(_, value) => Node::new("return", value.iter().map(expr_node).collect()),
+    }
+}
+
+fn function_node(name: &str, params: &[Token], body: &[Stmt]) -> Node {
+    let params: Vec<&str> = params.iter().map(|param| param.lexeme.as_str()).collect();
+    Node::new(
+        format!("fun {}({})", name, params.join(", ")),
+        body.iter().map(stmt_node).collect(),
+    )
+}
+
+fn expr_node(expr: &Expr) -> Node {
+    match expr {
+        Expr::Literal(literal) => Node::leaf(format!("{:?}", Value::from(literal.clone()))),
+        Expr::Unary(operator, right) => Node::new(operator.lexeme.clone(), vec![expr_node(right)]),
+        Expr::Binary(left, operator, right) | Expr::Logical(left, operator, right) => Node::new(
+            operator.lexeme.clone(),
+            vec![expr_node(left), expr_node(right)],
+        ),
+        Expr::Grouping(expr) => Node::new("group", vec![expr_node(expr)]),
+        Expr::Var(name, _) => Node::leaf(name.lexeme.clone()),
+        Expr::Assign(name, value, _) => {
+            Node::new("=", vec![Node::leaf(name.lexeme.clone()), expr_node(value)])
+        }
+        Expr::Call(callee, _, args) => {
+            let mut children = vec![expr_node(callee)];
+            children.extend(args.iter().map(expr_node));
+            Node::new("call", children)
+        }
+        Expr::Get(object, name) => {
+            Node::new(format!("get {}", name.lexeme), vec![expr_node(object)])
+        }
+        Expr::Set(object, name, value) => Node::new(
+            format!("set {}", name.lexeme),
+            vec![expr_node(object), expr_node(value)],
+        ),
+        Expr::This(..) => Node::leaf("this"),
+        Expr::Super(_, method, _) => Node::leaf(format!("super.{}", method.lexeme)),
+        Expr::List(_, elements) => Node::new("list", elements.iter().map(expr_node).collect()),
+        Expr::Lambda(_, params, body) => function_node("", params, body),
+        Expr::Map(_, entries) => Node::new(
+            "map",
+            entries
+                .iter()
+                .map(|(key, value)| Node::new(":", vec![expr_node(key), expr_node(value)]))
+                .collect(),
+        ),
+        Expr::Index(object, _, index) => {
+            Node::new("index", vec![expr_node(object), expr_node(index)])
+        }
+        Expr::SetIndex(object, _, index, value) => Node::new(
+            "set index",
+            vec![expr_node(object), expr_node(index), expr_node(value)],
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    const SOURCE: &str = "var a = 1; var b;
+fun add(x, y) { return x + y * 2; }
+if (a) print add(a, -3); else a = \"b\";";
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let (statements, errors) = Parser::new(tokens).parse();
+        assert!(errors.is_empty());
+        statements
+    }
+
+    #[test]
+    fn test_tokens() {
+        let tokens = Scanner::new("var a =\n  \"b\";".to_string()).scan_tokens();
+
+        assert_eq!(
+            super::tokens(&tokens),
+            "1:1      Var           var
+1:5      Identifier    a
+1:7      Equal         =
+2:3      String_       \"b\"
+2:6      Semicolon     ;
+2:7      Eof\n"
+        );
+    }
+
+    #[test]
+    fn test_ast_sexpr() {
+        assert_eq!(
+            ast(&parse(SOURCE), AstFormat::SExpr),
+            "(var a 1)
+(var b)
+(fun add(x, y) (return (+ x (* y 2))))
+(if a (print (call add a (- 3))) (expr (= a \"b\")))
+"
+        );
+    }
+
+    #[test]
+    fn test_ast_tree() {
+        assert_eq!(
+            ast(
+                &parse("class B < A { m() { this.x = super.m; } }"),
+                AstFormat::Tree
+            ),
+            "class B < A
+  fun m()
+    expr
+      set x
+        this
+        super.m
+"
+        );
+    }
+}
@@ -1,42 +1,134 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 use crate::ast::Value;
-use crate::error::RoxError;
+use crate::error::{RoxError, StackTrace};
+use crate::gc::{self, Trace};
+use crate::intern::intern;
 use crate::token::Token;
 
-#[derive(Clone)]
 pub struct Environment {
-    values: HashMap<String, Value>,
-    enclosing: Option<Box<Environment>>,
+    values: HashMap<Rc<str>, Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Trace for Environment {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        for value in self.values.values() {
+            value.trace(visit);
+        }
+        if let Some(enclosing) = &self.enclosing {
+            visit(gc::address(enclosing));
+        }
+    }
+
+    fn clear(&mut self) {
+        self.values.clear();
+        self.enclosing = None;
+    }
 }
 
 impl Environment {
-    pub fn new(enclosing: Option<Box<Environment>>) -> Self {
+    pub fn new(enclosing: Option<Rc<RefCell<Environment>>>) -> Self {
         Self {
             values: HashMap::new(),
             enclosing,
         }
     }
 
-    pub fn define(&mut self, name: String, value: Value) {
-        self.values.insert(name, value);
+    pub fn define(&mut self, name: &str, value: Value) {
+        self.values.insert(intern(name), value);
     }
 
-    pub fn get(&mut self, name: &Token) -> Result<Value, RoxError> {
-        if self.values.contains_key(&name.lexeme) {
-            return Ok(self.values.get(&name.lexeme).unwrap().clone());
-        } else if self.enclosing.as_ref().is_some() {
-            self.enclosing.as_mut().unwrap().get(&name)
+    pub fn get(&self, name: &Token) -> Result<Value, RoxError> {
+        if let Some(value) = self.values.get(name.lexeme.as_str()) {
+            Ok(value.clone())
+        } else if let Some(enclosing) = self.enclosing.as_ref() {
+            enclosing.borrow().get(name)
         } else {
-            Err(RoxError::UndefinedVariableError(name.clone()))
+            Err(RoxError::UndefinedVariableError(
+                name.clone(),
+                StackTrace::default(),
+            ))
         }
     }
 
-    pub fn assign(&mut self, name: Token, value: Value) {
-        if self.values.contains_key(&name.lexeme) {
-            self.values.insert(name.lexeme, value);
+    pub fn assign(&mut self, name: Token, value: Value) -> Result<(), RoxError> {
+        if let Some(slot) = self.values.get_mut(name.lexeme.as_str()) {
+            *slot = value;
+            Ok(())
+        } else if let Some(enclosing) = self.enclosing.as_ref() {
+            enclosing.borrow_mut().assign(name, value)
+        } else {
+            Err(RoxError::UndefinedVariableError(
+                name,
+                StackTrace::default(),
+            ))
         }
     }
+
+    /// Reads `name` from the scope exactly `distance` levels above `environment`.
+    pub fn get_at(
+        environment: &Rc<RefCell<Environment>>,
+        distance: usize,
+        name: &Token,
+    ) -> Result<Value, RoxError> {
+        Self::ancestor(environment, distance)
+            .borrow()
+            .values
+            .get(name.lexeme.as_str())
+            .cloned()
+            .ok_or_else(|| RoxError::UndefinedVariableError(name.clone(), StackTrace::default()))
+    }
+
+    /// Assigns `name` in the scope exactly `distance` levels above `environment`.
+    pub fn assign_at(
+        environment: &Rc<RefCell<Environment>>,
+        distance: usize,
+        name: Token,
+        value: Value,
+    ) -> Result<(), RoxError> {
+        let ancestor = Self::ancestor(environment, distance);
+        let mut ancestor = ancestor.borrow_mut();
+        match ancestor.values.get_mut(name.lexeme.as_str()) {
+            Some(slot) => {
+                *slot = value;
+                Ok(())
+            }
+            None => Err(RoxError::UndefinedVariableError(
+                name,
+                StackTrace::default(),
+            )),
+        }
+    }
+
+    fn ancestor(
+        environment: &Rc<RefCell<Environment>>,
+        distance: usize,
+    ) -> Rc<RefCell<Environment>> {
+        let mut environment = Rc::clone(environment);
+        for _ in 0..distance {
+            let enclosing = environment
+                .borrow()
+                .enclosing
+                .clone()
+                .expect("Resolved scope depth exceeds the environment chain.");
+            environment = enclosing;
+        }
+        environment
+    }
+
+    /// All bindings defined directly in this scope, sorted by name.
+    pub fn bindings(&self) -> Vec<(Rc<str>, Value)> {
+        let mut bindings: Vec<(Rc<str>, Value)> = self
+            .values
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        bindings.sort_by(|a, b| a.0.cmp(&b.0));
+        bindings
+    }
 }
 
 #[cfg(test)]
@@ -49,18 +141,61 @@ mod tests {
     fn test_environment() {
         let mut env = Environment::new(None);
         let token = Token::new(TokenType::Number, "a", Some(Literal::Number(5.0)), 1);
-        env.define("a".to_string(), Value::Number(5.0));
-        let res = env.get(&token);
+        env.define("a", Value::Number(5.0));
         assert_eq!(env.get(&token).unwrap(), Value::Number(5.0));
     }
 
     #[test]
     fn test_enclosing_environment() {
-        let mut enclosing_env = Environment::new(None);
-        enclosing_env.define("a".to_string(), Value::Number(5.0));
-        let mut env = Environment::new(Some(Box::new(enclosing_env.clone())));
+        let enclosing_env = Rc::new(RefCell::new(Environment::new(None)));
+        enclosing_env.borrow_mut().define("a", Value::Number(5.0));
+        let env = Environment::new(Some(Rc::clone(&enclosing_env)));
         let token = Token::new(TokenType::Number, "a", Some(Literal::Number(5.0)), 1);
 
         assert_eq!(env.get(&token).unwrap(), Value::Number(5.0));
     }
+
+    #[test]
+    fn test_assign_enclosing_environment() {
+        let enclosing_env = Rc::new(RefCell::new(Environment::new(None)));
+        enclosing_env.borrow_mut().define("a", Value::Number(5.0));
+        let mut env = Environment::new(Some(Rc::clone(&enclosing_env)));
+        let token = Token::new(TokenType::Identifier, "a", None, 1);
+
+        env.assign(token.clone(), Value::Number(6.0)).unwrap();
+
+        assert_eq!(
+            enclosing_env.borrow().get(&token).unwrap(),
+            Value::Number(6.0)
+        );
+    }
+
+    #[test]
+    fn test_assign_undefined_variable() {
+        let mut env = Environment::new(None);
+        let token = Token::new(TokenType::Identifier, "a", None, 1);
+
+        assert!(env.assign(token, Value::Nil).is_err());
+    }
+
+    #[test]
+    fn test_get_and_assign_at_distance() {
+        let globals = Rc::new(RefCell::new(Environment::new(None)));
+        globals.borrow_mut().define("a", Value::Number(1.0));
+        let inner = Rc::new(RefCell::new(Environment::new(Some(Rc::clone(&globals)))));
+        inner.borrow_mut().define("a", Value::Number(2.0));
+        let token = Token::new(TokenType::Identifier, "a", None, 1);
+
+        assert_eq!(
+            Environment::get_at(&inner, 0, &token).unwrap(),
+            Value::Number(2.0)
+        );
+        assert_eq!(
+            Environment::get_at(&inner, 1, &token).unwrap(),
+            Value::Number(1.0)
+        );
+
+        Environment::assign_at(&inner, 1, token.clone(), Value::Number(3.0)).unwrap();
+        assert_eq!(globals.borrow().get(&token).unwrap(), Value::Number(3.0));
+    }
 }
@@ -1,55 +1,392 @@
 use std::fmt;
 
-use crate::token::Token;
+use crate::ast::Value;
 use crate::token::TokenType::Eof;
+use crate::token::{Span, Token};
 
 #[derive(Debug)]
 pub enum RoxError {
-    UnexpectedCharacterError(String),
+    /// A character or unterminated string the scanner couldn't turn into a token, with the
+    /// line it starts on.
+    ScanError(usize, Span, String),
     ParseError(Token, String),
-    RuntimeError(String),
-    UndefinedVariableError(Token),
+    ResolveError(Token, String),
+    CompileError(Token, String),
+    /// A runtime error, with the calls in progress when it happened, innermost first. The
+    /// trace is empty for errors outside of any function.
+    RuntimeError(Token, String, StackTrace),
+    /// Raised by a native function, which doesn't know where it was called from. The call
+    /// turns it into a `RuntimeError` at its parenthesis.
+    NativeError(String),
+    /// A call made by the host that failed before getting into any script, such as one with
+    /// the wrong number of arguments. There is no line to report: the call isn't in a script.
+    HostCallError(String),
+    /// A script file that couldn't be read.
+    IoError(String),
+    /// Not a real error: carries a `return` value up to the enclosing call.
+    Return(Box<Value>),
+    /// Not a real error: unwinds to the enclosing loop and leaves it.
+    Break,
+    /// Not a real error: unwinds to the enclosing loop and starts its next iteration.
+    Continue,
+    /// A `throw` that nothing caught: the thrown value, the `throw` keyword, and the calls
+    /// in progress when it was thrown.
+    Throw(Box<Value>, Token, StackTrace),
+    UndefinedVariableError(Token, StackTrace),
     InvalidAssignmentError(Token),
-    UnexpectedError,
-    MaxParameterLimitError,
+}
+
+/// The calls in progress when an error happened, innermost first. Boxed so that carrying one
+/// doesn't make every `Result<_, RoxError>` bigger.
+pub type StackTrace = Box<Vec<StackFrame>>;
+
+/// A call that was in progress when an exception was thrown: the function called, and the
+/// line it was called from, or `None` if the host called it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StackFrame {
+    pub function: String,
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "at {} (line {})", self.function, line),
+            None => write!(f, "at {} (called by the host)", self.function),
+        }
+    }
 }
 
 impl fmt::Display for RoxError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message())?;
+        for line in trace_lines(self.trace()) {
+            write!(f, "\n{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl RoxError {
+    /// What went wrong and where, without the stack trace.
+    pub fn message(&self) -> String {
         match self {
-            RoxError::UnexpectedCharacterError(line_str) => {
-                write!(f, "Unexpected character at {}", line_str)
+            RoxError::ScanError(line, _, message) => {
+                format!("[line {}] Error: {}", line, message)
             }
-            RoxError::ParseError(token, message) => {
+            RoxError::ParseError(token, message)
+            | RoxError::ResolveError(token, message)
+            | RoxError::CompileError(token, message) => {
                 if token.token_type == Eof {
-                    return write!(f, "{} at end {}", token.line, message);
+                    format!("{} at end {}", token.line, message)
                 } else {
-                    return write!(f, "{} at '{}' {}", token.line, token.lexeme, message);
+                    format!("{} at '{}' {}", token.line, token.lexeme, message)
                 }
             }
-            RoxError::UndefinedVariableError(token) => {
-                write!(f, "Undefined variable '{}'.", token.lexeme)
+            RoxError::UndefinedVariableError(token, _) => {
+                format!("Undefined variable '{}'.", token.lexeme)
+            }
+            RoxError::RuntimeError(token, message, _) => {
+                format!("{}\n[line {}]", message, token.line)
             }
-            RoxError::RuntimeError(message) => {
-                write!(f, "{}", message)
+            RoxError::NativeError(message)
+            | RoxError::HostCallError(message)
+            | RoxError::IoError(message) => message.clone(),
+            RoxError::Return(value) => format!("Unhandled return of {}.", value),
+            RoxError::Break => "Unhandled 'break'.".to_string(),
+            RoxError::Continue => "Unhandled 'continue'.".to_string(),
+            RoxError::Throw(value, keyword, _) => {
+                format!("Uncaught exception: {}\n[line {}]", value, keyword.line)
+            }
+            RoxError::InvalidAssignmentError(_) => "Invalid assignment target.".to_string(),
+        }
+    }
+
+    /// The calls in progress when a runtime error happened or an exception was thrown,
+    /// innermost first.
+    pub fn trace(&self) -> &[StackFrame] {
+        match self {
+            RoxError::RuntimeError(_, _, trace)
+            | RoxError::UndefinedVariableError(_, trace)
+            | RoxError::Throw(_, _, trace) => trace,
+            _ => &[],
+        }
+    }
+
+    /// Fills in the trace of a runtime error that doesn't have one yet. Backends call this as
+    /// the error leaves the innermost function, when `trace` still sees every call.
+    pub fn traced(mut self, trace: impl FnOnce() -> StackTrace) -> RoxError {
+        if let RoxError::RuntimeError(_, _, frames) | RoxError::UndefinedVariableError(_, frames) =
+            &mut self
+        {
+            if frames.is_empty() {
+                *frames = trace();
+            }
+        }
+        self
+    }
+
+    /// Whether the error stopped a running script, as opposed to one that was rejected
+    /// before it ran.
+    pub fn is_runtime(&self) -> bool {
+        matches!(
+            self,
+            RoxError::RuntimeError(..)
+                | RoxError::NativeError(_)
+                | RoxError::HostCallError(_)
+                | RoxError::UndefinedVariableError(..)
+                | RoxError::Return(_)
+                | RoxError::Break
+                | RoxError::Continue
+                | RoxError::Throw(..)
+        )
+    }
+}
+
+/// Reports errors against the source they came from: a headline, then the offending line with
+/// the token underlined, rustc style.
+pub struct ErrorHandler<'a> {
+    source: &'a str,
+}
+
+impl<'a> ErrorHandler<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self { source }
+    }
+
+    pub fn error(&self, error: &RoxError) {
+        eprintln!("{}", self.render(error))
+    }
+
+    pub fn render(&self, error: &RoxError) -> String {
+        let headline = match error {
+            RoxError::ParseError(token, message)
+            | RoxError::ResolveError(token, message)
+            | RoxError::CompileError(token, message) => {
+                let location = if token.token_type == Eof {
+                    " at end".to_string()
+                } else {
+                    format!(" at '{}'", token.lexeme)
+                };
+                Self::report(token.line, location, message)
             }
             RoxError::InvalidAssignmentError(token) => {
-                write!(f, "Invalid assignment target {}.", token.lexeme)
+                Self::report(token.line, String::new(), &error.to_string())
             }
-            RoxError::MaxParameterLimitError => {
-                write!(f, "Can't have more than 255 parameters.")
+            RoxError::ScanError(..)
+            | RoxError::RuntimeError(..)
+            | RoxError::HostCallError(_)
+            | RoxError::Throw(..) => error.message(),
+            RoxError::UndefinedVariableError(token, _) => {
+                format!("{}\n[line {}]", error.message(), token.line)
             }
-            RoxError::UnexpectedError => {
-                write!(f, "Unexpected error while parsing")
+            _ => format!("Error: {}", error.message()),
+        };
+
+        let snippet = match error {
+            RoxError::ScanError(line, span, _) => self.snippet(*line, *span, None, hint(error)),
+            RoxError::ParseError(token, _)
+            | RoxError::ResolveError(token, _)
+            | RoxError::CompileError(token, _)
+            | RoxError::RuntimeError(token, ..)
+            | RoxError::UndefinedVariableError(token, _)
+            | RoxError::InvalidAssignmentError(token)
+            | RoxError::Throw(_, token, _) => {
+                self.snippet(token.line, token.span, Some(&token.lexeme), hint(error))
             }
+            RoxError::NativeError(_)
+            | RoxError::HostCallError(_)
+            | RoxError::IoError(_)
+            | RoxError::Return(_)
+            | RoxError::Break
+            | RoxError::Continue => None,
+        };
+
+        let mut rendered = match snippet {
+            Some(snippet) => format!("{}\n{}", headline, snippet),
+            None => headline,
+        };
+        for line in trace_lines(error.trace()) {
+            rendered.push('\n');
+            rendered.push_str(&line);
         }
+        rendered
+    }
+
+    fn report(line: usize, location: String, message: &str) -> String {
+        format!("[line {}] Error{}: {}", line, location, message)
+    }
+
+    /// Renders source line `line` with carets under `span`, or `None` if the span can't be
+    /// placed in this source.
+    fn snippet(
+        &self,
+        line: usize,
+        span: Span,
+        lexeme: Option<&str>,
+        hint: Option<&str>,
+    ) -> Option<String> {
+        // Tokens made up by the interpreter have no position.
+        if span.column == 0 {
+            return None;
+        }
+        // A REPL entry can fail inside a function defined by an earlier entry, whose spans
+        // point into a different source.
+        if lexeme.is_some() && self.source.get(span.start..span.end) != lexeme {
+            return None;
+        }
+
+        let text = self.source.lines().nth(line.checked_sub(1)?).unwrap_or("");
+        let indent: String = text
+            .chars()
+            .take(span.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = self.source[span.start..span.end]
+            .chars()
+            .take_while(|&c| c != '\n')
+            .count()
+            .max(1);
+
+        let gutter = " ".repeat(line.to_string().len());
+        let mut snippet = format!(
+            "{gutter} |\n{line} | {text}\n{gutter} | {indent}{carets}",
+            gutter = gutter,
+            line = line,
+            text = text,
+            indent = indent,
+            carets = "^".repeat(width)
+        );
+        if let Some(hint) = hint {
+            snippet.push(' ');
+            snippet.push_str(hint);
+        }
+        Some(snippet)
     }
 }
 
-pub struct ErrorHandler {}
+/// One line per frame of a trace. A run of identical frames, as deep recursion leaves, is cut
+/// short after a few of them.
+fn trace_lines(trace: &[StackFrame]) -> Vec<String> {
+    const REPEATS_SHOWN: usize = 3;
+
+    let mut lines = Vec::new();
+    let mut i = 0;
+    while i < trace.len() {
+        let run = trace[i..]
+            .iter()
+            .take_while(|frame| **frame == trace[i])
+            .count();
+        for _ in 0..run.min(REPEATS_SHOWN) {
+            lines.push(format!("  {}", trace[i]));
+        }
+        if run > REPEATS_SHOWN {
+            lines.push(format!(
+                "  [previous frame repeated {} more times]",
+                run - REPEATS_SHOWN
+            ));
+        }
+        i += run;
+    }
+    lines
+}
+
+/// A short note printed next to the carets, for errors whose message alone doesn't say what
+/// to do about them.
+fn hint(error: &RoxError) -> Option<&'static str> {
+    match error {
+        RoxError::ScanError(_, _, message) if message == "Unterminated string." => {
+            Some("this string is never closed")
+        }
+        RoxError::ScanError(..) => Some("not part of rox syntax"),
+        RoxError::ParseError(token, _) if token.token_type == Eof => Some("the script ends here"),
+        RoxError::InvalidAssignmentError(_) => Some("only variables and fields can be assigned"),
+        RoxError::UndefinedVariableError(..) => Some("declare it with 'var' first"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn render_parse_errors(source: &str) -> Vec<String> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let (_, errors) = Parser::new(tokens).parse();
+        let handler = ErrorHandler::new(source);
+        errors.iter().map(|err| handler.render(err)).collect()
+    }
+
+    #[test]
+    fn test_render_parse_error_snippet() {
+        assert_eq!(
+            render_parse_errors("var a = 1;\nprint (a;"),
+            vec![
+                "[line 2] Error at ';': Expect ')' after expression.\n  \
+                 |\n2 | print (a;\n  |         ^"
+            ]
+        );
+        assert_eq!(
+            render_parse_errors("print \"one\" + two = 3;"),
+            vec![
+                "[line 1] Error: Invalid assignment target.\n  \
+                 |\n1 | print \"one\" + two = 3;\n  |                   ^ \
+                 only variables and fields can be assigned"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_underlines_whole_token() {
+        let mut scanner = Scanner::new("\tprint \"unterminated".to_string());
+        scanner.scan_tokens();
+        let handler = ErrorHandler::new("\tprint \"unterminated");
+
+        assert_eq!(
+            handler.render(&scanner.errors[0]),
+            "[line 1] Error: Unterminated string.\n  \
+             |\n1 | \tprint \"unterminated\n  | \t      ^^^^^^^^^^^^^ this string is never closed"
+        );
+    }
+
+    #[test]
+    fn test_render_trace() {
+        let frame = |function: &str, line| StackFrame {
+            function: function.to_string(),
+            line,
+        };
+        let mut trace = vec![frame("f", Some(2)); 5];
+        trace.push(frame("main", Some(9)));
+        trace.push(frame("run", None));
+        let token = Token::new(crate::token::TokenType::Identifier, "x", None, 1);
+        let handler = ErrorHandler::new("print x;");
+
+        assert_eq!(
+            handler.render(&RoxError::RuntimeError(
+                token,
+                "Oops.".to_string(),
+                trace.into()
+            )),
+            "Oops.\n[line 1]\n  at f (line 2)\n  at f (line 2)\n  at f (line 2)\n  \
+             [previous frame repeated 2 more times]\n  at main (line 9)\n  \
+             at run (called by the host)"
+        );
+    }
+
+    #[test]
+    fn test_render_without_position() {
+        let token = Token::new(crate::token::TokenType::Identifier, "x", None, 3);
+        let handler = ErrorHandler::new("print x;");
 
-impl ErrorHandler {
-    fn report(line: i64, location: String, message: String) {
-        println!("[line {}] Error {} ': {}", line, location, message)
+        assert_eq!(
+            handler.render(&RoxError::RuntimeError(
+                token,
+                "Oops.".to_string(),
+                StackTrace::default()
+            )),
+            "Oops.\n[line 3]"
+        );
     }
 }
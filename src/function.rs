@@ -2,41 +2,146 @@ use crate::ast::Stmt;
 use crate::ast::Value;
 use crate::environment::Environment;
 use crate::error::RoxError;
+use crate::gc::{self, Heap, Trace};
+use crate::interpreter::Interpreter;
+use crate::native::{Arity, NativeFn};
 use crate::token::Token;
-use crate::Interpreter;
+use crate::token::TokenType::This;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 #[derive(Clone)]
 pub enum RoxFunction {
     Native {
-        arity: usize,
-        body: Box<fn(&Vec<Value>) -> Value>,
+        name: Rc<str>,
+        arity: Arity,
+        body: Rc<NativeFn>,
     },
     User {
         name: Token,
         params: Vec<Token>,
-        body: Vec<Stmt>,
+        body: Rc<[Stmt]>,
+        closure: Rc<RefCell<Environment>>,
+        is_initializer: bool,
     },
 }
 
+impl Trace for RoxFunction {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        if let RoxFunction::User { closure, .. } = self {
+            visit(gc::address(closure));
+        }
+    }
+}
+
 impl RoxFunction {
+    pub fn native(
+        name: &str,
+        arity: Arity,
+        body: impl Fn(&[Value]) -> Result<Value, RoxError> + 'static,
+    ) -> Self {
+        RoxFunction::Native {
+            name: name.into(),
+            arity,
+            body: Rc::new(body),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            RoxFunction::Native { name, .. } => name,
+            RoxFunction::User { name, .. } => &name.lexeme,
+        }
+    }
+
+    /// How many arguments the function takes. A user function takes exactly one per parameter.
+    pub fn arity(&self) -> Arity {
+        match self {
+            RoxFunction::Native { arity, .. } => *arity,
+            RoxFunction::User { params, .. } => Arity::Fixed(params.len()),
+        }
+    }
+
+    /// Whether both are the same function object: the same native, or the same declaration
+    /// closed over the same environment.
+    pub fn ptr_eq(&self, other: &RoxFunction) -> bool {
+        match (self, other) {
+            (RoxFunction::Native { body: left, .. }, RoxFunction::Native { body: right, .. }) => {
+                Rc::ptr_eq(left, right)
+            }
+            (
+                RoxFunction::User {
+                    body: left,
+                    closure: left_closure,
+                    ..
+                },
+                RoxFunction::User {
+                    body: right,
+                    closure: right_closure,
+                    ..
+                },
+            ) => Rc::ptr_eq(left, right) && Rc::ptr_eq(left_closure, right_closure),
+            _ => false,
+        }
+    }
+
+    /// Returns a copy of this method whose scope has `this` bound to `instance`.
+    pub fn bind(&self, instance: Value, heap: &mut Heap) -> RoxFunction {
+        match self {
+            RoxFunction::Native { .. } => self.clone(),
+            RoxFunction::User {
+                name,
+                params,
+                body,
+                closure,
+                is_initializer,
+            } => {
+                let mut environment = Environment::new(Some(Rc::clone(closure)));
+                environment.define("this", instance);
+                RoxFunction::User {
+                    name: name.clone(),
+                    params: params.clone(),
+                    body: Rc::clone(body),
+                    closure: heap.environment(environment),
+                    is_initializer: *is_initializer,
+                }
+            }
+        }
+    }
+
     pub fn call(
         &self,
         interpreter: &mut Interpreter,
-        arguments: &Vec<Value>,
+        arguments: &[Value],
     ) -> Result<Value, RoxError> {
         match self {
-            RoxFunction::Native { body, .. } => Ok(body(arguments)),
+            RoxFunction::Native { body, .. } => body(arguments),
             RoxFunction::User {
-                name, params, body, ..
+                name,
+                params,
+                body,
+                closure,
+                is_initializer,
             } => {
-                let mut environment = Environment::new(Some(Box::new(interpreter.globals.clone())));
-                for i in 0..params.len() {
-                    environment.define(params[i].lexeme.clone(), arguments[i].clone());
+                let mut environment = Environment::new(Some(Rc::clone(closure)));
+                for (param, argument) in params.iter().zip(arguments) {
+                    environment.define(&param.lexeme, argument.clone());
                 }
 
-                interpreter.execute_block(body.clone(), environment);
+                let environment = interpreter.heap.environment(environment);
+                let value = match interpreter.execute_block(body.to_vec(), environment) {
+                    Ok(()) => Value::Nil,
+                    Err(RoxError::Return(value)) => *value,
+                    Err(err) => return Err(err),
+                };
+
+                if *is_initializer {
+                    return closure
+                        .borrow()
+                        .get(&Token::new(This, "this", None, name.line));
+                }
 
-                Ok(Value::Nil)
+                Ok(value)
             }
         }
     }
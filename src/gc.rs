@@ -0,0 +1,506 @@
+//! Cycle collection for the objects scripts can link together.
+//!
+//! Objects are ordinary `Rc`s, so anything unreachable without a cycle is freed as soon as its
+//! last reference goes. The `Heap` keeps a weak registry of every environment, instance, list,
+//! map, class, closure and upvalue it allocated, and a collection finds the groups of them that
+//! are only kept alive by references among themselves.
+//!
+//! It does this by trial deletion, the way CPython's collector does: subtracting the
+//! references objects hold to each other from their reference counts leaves, for each object,
+//! how many references come from outside the heap. The interpreter's globals and environment
+//! chain, the VM's value stack and anything the Rust call stack is holding on to all count as
+//! outside references, so they are the roots without having to be listed. Everything not
+//! reachable from a root is garbage; clearing its contents breaks the cycles and lets `Rc`
+//! free it.
+
+use crate::ast::Value;
+use crate::class::{RoxClass, RoxInstance};
+use crate::environment::Environment;
+use crate::intern;
+use crate::list::List;
+use crate::map::{Map, MapRef};
+use crate::vm::{Closure, Upvalue};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+/// Number of live objects that triggers the first collection.
+const INITIAL_THRESHOLD: usize = 1024;
+
+/// Objects the collector looks inside of.
+pub trait Trace {
+    /// Calls `visit` with the `address` of every object this one holds an `Rc` to.
+    fn trace(&self, visit: &mut dyn FnMut(usize));
+
+    /// Drops the references this object holds, breaking any cycle that runs through it.
+    fn clear(&mut self) {}
+}
+
+/// Identifies a heap object by where its `Rc` points, which is what `Trace::trace` reports.
+pub fn address<T>(object: &Rc<T>) -> usize {
+    Rc::as_ptr(object) as *const () as usize
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GcStats {
+    /// Objects allocated through the heap.
+    pub allocated: usize,
+    pub collections: usize,
+    /// Objects freed by breaking cycles, as opposed to by their reference count dropping.
+    pub reclaimed: usize,
+    /// Objects still alive.
+    pub live: usize,
+}
+
+impl fmt::Display for GcStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "gc: {} allocated, {} collections, {} reclaimed from cycles, {} live",
+            self.allocated, self.collections, self.reclaimed, self.live
+        )
+    }
+}
+
+enum Tracked {
+    Environment(Weak<RefCell<Environment>>),
+    Instance(Weak<RefCell<RoxInstance>>),
+    Class(Weak<RoxClass>),
+    Closure(Weak<Closure>),
+    Upvalue(Weak<RefCell<Upvalue>>),
+    List(Weak<RefCell<Vec<Value>>>),
+    Map(Weak<RefCell<Map>>),
+}
+
+impl Tracked {
+    fn upgrade(&self) -> Option<Object> {
+        Some(match self {
+            Tracked::Environment(env) => Object::Environment(env.upgrade()?),
+            Tracked::Instance(instance) => Object::Instance(instance.upgrade()?),
+            Tracked::Class(class) => Object::Class(class.upgrade()?),
+            Tracked::Closure(closure) => Object::Closure(closure.upgrade()?),
+            Tracked::Upvalue(upvalue) => Object::Upvalue(upvalue.upgrade()?),
+            Tracked::List(list) => Object::List(list.upgrade()?),
+            Tracked::Map(map) => Object::Map(map.upgrade()?),
+        })
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            Tracked::Environment(env) => env.strong_count() > 0,
+            Tracked::Instance(instance) => instance.strong_count() > 0,
+            Tracked::Class(class) => class.strong_count() > 0,
+            Tracked::Closure(closure) => closure.strong_count() > 0,
+            Tracked::Upvalue(upvalue) => upvalue.strong_count() > 0,
+            Tracked::List(list) => list.strong_count() > 0,
+            Tracked::Map(map) => map.strong_count() > 0,
+        }
+    }
+}
+
+/// A tracked object, upgraded for the length of a collection.
+enum Object {
+    Environment(Rc<RefCell<Environment>>),
+    Instance(Rc<RefCell<RoxInstance>>),
+    Class(Rc<RoxClass>),
+    Closure(Rc<Closure>),
+    Upvalue(Rc<RefCell<Upvalue>>),
+    List(List),
+    Map(MapRef),
+}
+
+impl Object {
+    fn address(&self) -> usize {
+        match self {
+            Object::Environment(env) => address(env),
+            Object::Instance(instance) => address(instance),
+            Object::Class(class) => address(class),
+            Object::Closure(closure) => address(closure),
+            Object::Upvalue(upvalue) => address(upvalue),
+            Object::List(list) => address(list),
+            Object::Map(map) => address(map),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Environment(env) => Rc::strong_count(env),
+            Object::Instance(instance) => Rc::strong_count(instance),
+            Object::Class(class) => Rc::strong_count(class),
+            Object::Closure(closure) => Rc::strong_count(closure),
+            Object::Upvalue(upvalue) => Rc::strong_count(upvalue),
+            Object::List(list) => Rc::strong_count(list),
+            Object::Map(map) => Rc::strong_count(map),
+        }
+    }
+
+    /// Traces the object, or returns `false` if it is mutably borrowed and can't be looked at.
+    fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool {
+        fn trace_cell<T: Trace>(cell: &RefCell<T>, visit: &mut dyn FnMut(usize)) -> bool {
+            match cell.try_borrow() {
+                Ok(object) => {
+                    object.trace(visit);
+                    true
+                }
+                Err(_) => false,
+            }
+        }
+
+        match self {
+            Object::Environment(env) => trace_cell(env, visit),
+            Object::Instance(instance) => trace_cell(instance, visit),
+            Object::Class(class) => {
+                class.trace(visit);
+                true
+            }
+            Object::Closure(closure) => {
+                closure.trace(visit);
+                true
+            }
+            Object::Upvalue(upvalue) => trace_cell(upvalue, visit),
+            Object::List(list) => trace_cell(list, visit),
+            Object::Map(map) => trace_cell(map, visit),
+        }
+    }
+
+    fn clear(&self) {
+        fn clear_cell<T: Trace>(cell: &RefCell<T>) {
+            if let Ok(mut object) = cell.try_borrow_mut() {
+                object.clear();
+            }
+        }
+
+        match self {
+            Object::Environment(env) => clear_cell(env),
+            Object::Instance(instance) => clear_cell(instance),
+            Object::Upvalue(upvalue) => clear_cell(upvalue),
+            Object::List(list) => clear_cell(list),
+            Object::Map(map) => clear_cell(map),
+            // Immutable, but every cycle through one also runs through something clearable.
+            Object::Class(_) | Object::Closure(_) => {}
+        }
+    }
+}
+
+/// Allocates the objects that can form reference cycles and collects the cycles that become
+/// unreachable.
+pub struct Heap {
+    objects: Vec<Tracked>,
+    next_gc: usize,
+    /// Collect before every allocation, to shake out objects that are missing a reference.
+    pub stress: bool,
+    stats: GcStats,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+            next_gc: INITIAL_THRESHOLD,
+            stress: false,
+            stats: GcStats::default(),
+        }
+    }
+
+    pub fn environment(&mut self, environment: Environment) -> Rc<RefCell<Environment>> {
+        self.before_allocation();
+        let environment = Rc::new(RefCell::new(environment));
+        self.objects
+            .push(Tracked::Environment(Rc::downgrade(&environment)));
+        environment
+    }
+
+    pub fn instance(&mut self, instance: RoxInstance) -> Rc<RefCell<RoxInstance>> {
+        self.before_allocation();
+        let instance = Rc::new(RefCell::new(instance));
+        self.objects
+            .push(Tracked::Instance(Rc::downgrade(&instance)));
+        instance
+    }
+
+    pub fn class(&mut self, class: RoxClass) -> Rc<RoxClass> {
+        self.before_allocation();
+        let class = Rc::new(class);
+        self.objects.push(Tracked::Class(Rc::downgrade(&class)));
+        class
+    }
+
+    pub fn closure(&mut self, closure: Closure) -> Rc<Closure> {
+        self.before_allocation();
+        let closure = Rc::new(closure);
+        self.objects.push(Tracked::Closure(Rc::downgrade(&closure)));
+        closure
+    }
+
+    pub fn upvalue(&mut self, upvalue: Upvalue) -> Rc<RefCell<Upvalue>> {
+        self.before_allocation();
+        let upvalue = Rc::new(RefCell::new(upvalue));
+        self.objects.push(Tracked::Upvalue(Rc::downgrade(&upvalue)));
+        upvalue
+    }
+
+    pub fn list(&mut self, elements: Vec<Value>) -> List {
+        self.before_allocation();
+        let list = Rc::new(RefCell::new(elements));
+        self.objects.push(Tracked::List(Rc::downgrade(&list)));
+        list
+    }
+
+    pub fn map(&mut self, map: Map) -> MapRef {
+        self.before_allocation();
+        let map = Rc::new(RefCell::new(map));
+        self.objects.push(Tracked::Map(Rc::downgrade(&map)));
+        map
+    }
+
+    /// Starts tracking a list or map made outside the heap, such as by a native function.
+    /// Anything else is left alone.
+    pub fn adopt(&mut self, value: &Value) {
+        // The heap's own `Weak` is the only kind lists and maps ever get, so one without is
+        // untracked.
+        match value {
+            Value::List(list) if Rc::weak_count(list) == 0 => {
+                self.before_allocation();
+                self.objects.push(Tracked::List(Rc::downgrade(list)));
+            }
+            Value::Map(map) if Rc::weak_count(map) == 0 => {
+                self.before_allocation();
+                self.objects.push(Tracked::Map(Rc::downgrade(map)));
+            }
+            _ => {}
+        }
+    }
+
+    pub fn stats(&self) -> GcStats {
+        GcStats {
+            live: self
+                .objects
+                .iter()
+                .filter(|object| object.is_alive())
+                .count(),
+            ..self.stats
+        }
+    }
+
+    fn before_allocation(&mut self) {
+        self.stats.allocated += 1;
+        if self.stress || self.objects.len() >= self.next_gc {
+            self.collect();
+        }
+    }
+
+    /// Frees every object that is only reachable from other unreachable objects.
+    pub fn collect(&mut self) {
+        self.stats.collections += 1;
+
+        let objects: Vec<Object> = self.objects.iter().filter_map(Tracked::upgrade).collect();
+        let index: HashMap<usize, usize> = objects
+            .iter()
+            .enumerate()
+            .map(|(i, object)| (object.address(), i))
+            .collect();
+
+        // Start from each object's reference count, less the one `objects` holds, and take
+        // away the references coming from other heap objects. What is left comes from outside.
+        let mut external: Vec<usize> = objects.iter().map(|o| o.strong_count() - 1).collect();
+        let mut roots = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            let traced = object.trace(&mut |target| {
+                if let Some(&target) = index.get(&target) {
+                    external[target] -= 1;
+                }
+            });
+            if !traced {
+                // Something is in the middle of changing it, so it is certainly in use.
+                roots.push(i);
+            }
+        }
+        roots.extend((0..objects.len()).filter(|&i| external[i] > 0));
+
+        let mut reachable = vec![false; objects.len()];
+        while let Some(i) = roots.pop() {
+            if reachable[i] {
+                continue;
+            }
+            reachable[i] = true;
+            objects[i].trace(&mut |target| {
+                if let Some(&target) = index.get(&target) {
+                    roots.push(target);
+                }
+            });
+        }
+
+        for (object, reachable) in objects.iter().zip(&reachable) {
+            if !reachable {
+                object.clear();
+                self.stats.reclaimed += 1;
+            }
+        }
+        // Dropping our handles frees whatever the clearing cut loose, which may leave names
+        // that only the interner still holds.
+        drop(objects);
+        intern::prune();
+
+        self.objects.retain(Tracked::is_alive);
+        self.next_gc = INITIAL_THRESHOLD.max(self.objects.len() * 2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::interpreter::Interpreter;
+    use crate::map::Key;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use crate::scanner::Scanner;
+    use crate::token::{Token, TokenType};
+    use crate::vm::Vm;
+
+    // Each call to `counter` leaves behind a closure that can reach itself.
+    const CYCLES: &str = "fun counter() {
+  var count = 0;
+  fun increment() { count = count + 1; return increment; }
+  return increment;
+}
+for (var i = 0; i < 10; i = i + 1) counter()();
+var total = 0;
+fun sum(n) { if (n > 0) { total = total + n; sum(n - 1); } }
+sum(5);";
+
+    fn parse(source: &str) -> Vec<crate::ast::Stmt> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let (mut statements, errors) = Parser::new(tokens).parse();
+        assert!(errors.is_empty());
+        Resolver::new().resolve(&mut statements).unwrap();
+        statements
+    }
+
+    fn global(name: &str) -> Token {
+        Token::new(TokenType::Identifier, name, None, 1)
+    }
+
+    #[test]
+    fn test_collect_reclaims_cycles() {
+        let mut heap = Heap::new();
+        let environment = heap.environment(Environment::new(None));
+        let inner = heap.environment(Environment::new(Some(Rc::clone(&environment))));
+        // The outer environment holds on to the inner one, which encloses it.
+        environment.borrow_mut().define(
+            "f",
+            Value::Callable(crate::function::RoxFunction::User {
+                name: global("f"),
+                params: Vec::new(),
+                body: Vec::new().into(),
+                closure: Rc::clone(&inner),
+                is_initializer: false,
+            }),
+        );
+        let kept = heap.environment(Environment::new(None));
+        drop(environment);
+        drop(inner);
+
+        heap.collect();
+
+        let stats = heap.stats();
+        assert_eq!(stats.reclaimed, 2);
+        assert_eq!(stats.live, 1);
+        assert_eq!(Rc::strong_count(&kept), 1);
+    }
+
+    #[test]
+    fn test_collect_reclaims_list_cycles() {
+        let mut heap = Heap::new();
+        let list = heap.list(Vec::new());
+        list.borrow_mut().push(Value::List(Rc::clone(&list)));
+        // Lists made outside the heap are tracked once they are adopted.
+        let adopted = Value::from(vec![Value::List(Rc::clone(&list))]);
+        heap.adopt(&adopted);
+        heap.adopt(&adopted);
+        drop(list);
+
+        heap.collect();
+        assert_eq!(heap.stats().reclaimed, 0);
+
+        drop(adopted);
+        heap.collect();
+        assert_eq!(heap.stats().reclaimed, 1);
+        assert_eq!(heap.stats().live, 0);
+    }
+
+    #[test]
+    fn test_collect_reclaims_map_cycles() {
+        let mut heap = Heap::new();
+        let map = heap.map(Map::new());
+        let list = heap.list(vec![Value::Map(Rc::clone(&map))]);
+        map.borrow_mut()
+            .insert(Key::Nil, Value::List(Rc::clone(&list)));
+        drop(list);
+
+        heap.collect();
+        assert_eq!(heap.stats().reclaimed, 0);
+
+        drop(map);
+        heap.collect();
+        assert_eq!(heap.stats().reclaimed, 2);
+        assert_eq!(heap.stats().live, 0);
+    }
+
+    #[test]
+    fn test_collect_keeps_reachable_objects() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .interpret(&parse(
+                "class A {} var a = A(); a.self = a; fun f() { return a; }",
+            ))
+            .unwrap();
+
+        interpreter.heap.collect();
+
+        assert_eq!(interpreter.heap.stats().reclaimed, 0);
+        let a = interpreter.globals.borrow().get(&global("a")).unwrap();
+        assert!(matches!(a, Value::Instance(_)));
+    }
+
+    #[test]
+    fn test_stress_interpreter() {
+        let mut interpreter = Interpreter::new();
+        interpreter.heap.stress = true;
+        let source = format!(
+            "{}\nclass Node {{ init() {{ this.next = this; }} }}\nNode(); Node();",
+            CYCLES
+        );
+        interpreter.interpret(&parse(&source)).unwrap();
+
+        let stats = interpreter.heap.stats();
+        // Every allocation but the globals and the error class, which come before `stress` is
+        // set.
+        assert_eq!(stats.collections, stats.allocated - 2);
+        // An environment for each counter, and the first instance. Nothing is allocated after
+        // the second one, so no collection gets to it.
+        assert_eq!(stats.reclaimed, 11);
+        assert_eq!(
+            interpreter.globals.borrow().get(&global("total")).unwrap(),
+            Value::Number(15.0)
+        );
+    }
+
+    #[test]
+    fn test_stress_vm() {
+        let script = Compiler::new().compile(&parse(CYCLES)).unwrap();
+        let mut vm = Vm::new();
+        vm.heap.stress = true;
+        vm.interpret(script).unwrap();
+
+        // A closure and upvalues for `count` and `increment` for each counter.
+        assert_eq!(vm.heap.stats().reclaimed, 30);
+    }
+}
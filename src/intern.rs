@@ -0,0 +1,86 @@
+//! A table of shared strings.
+//!
+//! Identifiers and string literals are interned when they are first seen, so every variable
+//! name and every evaluation of a literal refers to the same reference-counted buffer instead
+//! of a fresh copy. Two interned strings with the same contents are the same allocation, which
+//! lets equality checks compare pointers before falling back to comparing bytes.
+//!
+//! The table doesn't keep strings alive by itself: `prune` drops the ones nothing else refers
+//! to, and the heap calls it whenever it collects.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+thread_local! {
+    static STRINGS: RefCell<Interner> = RefCell::new(Interner::new());
+}
+
+/// Returns the shared copy of `string`, adding it to this thread's table if it is new.
+pub fn intern(string: &str) -> Rc<str> {
+    STRINGS.with(|strings| strings.borrow_mut().intern(string))
+}
+
+/// Drops every string in this thread's table that is no longer used outside of it.
+pub fn prune() {
+    STRINGS.with(|strings| strings.borrow_mut().prune())
+}
+
+#[derive(Debug, Default)]
+pub struct Interner {
+    strings: HashSet<Rc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, string: &str) -> Rc<str> {
+        if let Some(interned) = self.strings.get(string) {
+            return Rc::clone(interned);
+        }
+        let interned: Rc<str> = Rc::from(string);
+        self.strings.insert(Rc::clone(&interned));
+        interned
+    }
+
+    pub fn prune(&mut self) {
+        // The table's own reference is the last one left.
+        self.strings.retain(|string| Rc::strong_count(string) > 1);
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.strings.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern_shares_buffers() {
+        let mut interner = Interner::new();
+        let a = interner.intern("name");
+        let b = interner.intern(&String::from("name"));
+        let c = interner.intern("other");
+
+        assert!(Rc::ptr_eq(&a, &b));
+        assert!(!Rc::ptr_eq(&a, &c));
+        assert_eq!(interner.len(), 2);
+        assert!(Rc::ptr_eq(&intern("name"), &intern("name")));
+    }
+
+    #[test]
+    fn test_prune_drops_unused_strings() {
+        let mut interner = Interner::new();
+        let kept = interner.intern("kept");
+        interner.intern("temporary");
+
+        interner.prune();
+        assert_eq!(interner.len(), 1);
+        assert!(Rc::ptr_eq(&kept, &interner.intern("kept")));
+    }
+}
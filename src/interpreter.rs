@@ -1,248 +1,669 @@
 use crate::ast::{Expr, ExprVisitor, Stmt, StmtVisitor, Value};
+use crate::class::{exception_value, Method, RoxClass, RoxInstance};
 use crate::environment::Environment;
+use crate::error::{RoxError, StackFrame, StackTrace};
 use crate::function::RoxFunction;
+use crate::gc::Heap;
+use crate::map::{Key, Map};
+use crate::native::{self, Arity};
+use crate::output::Output;
 use crate::token::Literal;
 use crate::token::Token;
 use crate::token::TokenType::{
-    Bang, BangEqual, EqualEqual, Greater, GreaterEqual, Less, LessEqual, Minus, Or, Plus, Slash,
-    Star,
+    Bang, BangEqual, EqualEqual, Greater, GreaterEqual, Identifier, Less, LessEqual, Minus, Or,
+    Plus, Slash, Star, This,
 };
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// How deep calls can nest before the interpreter gives up with a stack overflow, rather than
+/// let Rust's own stack run out.
+const FRAMES_MAX: usize = 256;
 
 pub struct Interpreter {
-    environment: Environment,
-    pub globals: Environment,
+    environment: Rc<RefCell<Environment>>,
+    pub globals: Rc<RefCell<Environment>>,
+    pub heap: Heap,
+    pub output: Output,
+    // The calls in progress, outermost first.
+    frames: Vec<StackFrame>,
+    // The class of the values that runtime errors become when caught.
+    error_class: Rc<RoxClass>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
         let mut globals = Environment::new(None);
-        let clock: Value = Value::Callable(RoxFunction::Native {
-            arity: 0,
-            body: Box::new(|_args: &Vec<Value>| {
-                Value::Number(
-                    SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .expect("Could not retrieve time.")
-                        .as_millis() as f64,
-                )
-            }),
-        });
-        globals.define("clock".to_string(), clock);
+        for function in native::standard_library() {
+            let name = function.name().to_string();
+            globals.define(&name, Value::Callable(function));
+        }
+
+        let mut heap = Heap::new();
+        let globals = heap.environment(globals);
+        let error_class = heap.class(RoxClass::new("Error".to_string(), None, HashMap::new()));
         Self {
-            environment: globals.clone(),
+            environment: Rc::clone(&globals),
             globals,
+            heap,
+            output: Output::default(),
+            frames: Vec::new(),
+            error_class,
         }
     }
 
-    fn execute(&mut self, _stmt: &Stmt) {}
+    /// Defines a global function that runs `body`, which is passed the call's arguments once
+    /// their count has been checked against `arity`.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        body: impl Fn(&[Value]) -> Result<Value, RoxError> + 'static,
+    ) {
+        let function = RoxFunction::native(name, arity, body);
+        self.globals
+            .borrow_mut()
+            .define(name, Value::Callable(function));
+    }
+
+    /// Calls a function or class with evaluated arguments. Errors are reported at `paren`,
+    /// the call's closing parenthesis, which is `None` when the host makes the call.
+    pub fn call(
+        &mut self,
+        callee: Value,
+        arguments: &[Value],
+        paren: Option<&Token>,
+    ) -> Result<Value, RoxError> {
+        match callee {
+            Value::Callable(function) => {
+                Self::check_arity(function.arity(), arguments, paren)?;
+                let value =
+                    self.call_function(&function, arguments, paren)
+                        .map_err(|err| match err {
+                            RoxError::NativeError(message) => Self::call_error(paren, &message),
+                            err => err,
+                        })?;
+                self.heap.adopt(&value);
+                Ok(value)
+            }
+            Value::Class(class) => {
+                let initializer = class.find_method("init");
+                let arity = initializer.as_ref().map_or(Arity::Fixed(0), Method::arity);
+                Self::check_arity(arity, arguments, paren)?;
+
+                let instance =
+                    Value::Instance(self.heap.instance(RoxInstance::new(Rc::clone(&class))));
+                if let Some(initializer) = initializer {
+                    let initializer = initializer.bind(instance.clone(), &mut self.heap);
+                    self.call(initializer, arguments, paren)?;
+                }
+                Ok(instance)
+            }
+            _ => Err(Self::call_error(
+                paren,
+                "Can only call functions and classes.",
+            )),
+        }
+    }
 
-    pub fn execute_block(&mut self, statements: Vec<Stmt>, environment: Environment) {
-        let previous: Environment = self.environment.clone();
+    fn check_arity(
+        arity: Arity,
+        arguments: &[Value],
+        paren: Option<&Token>,
+    ) -> Result<(), RoxError> {
+        arity
+            .check(arguments.len())
+            .map_err(|message| Self::call_error(paren, &message))
+    }
 
-        self.environment = environment;
-        for statement in statements {
-            self.execute(&statement);
+    /// An error in making the call at `paren`, rather than inside the function it calls.
+    fn call_error(paren: Option<&Token>, message: &str) -> RoxError {
+        match paren {
+            Some(paren) => Self::runtime_error(paren, message),
+            None => RoxError::HostCallError(message.to_string()),
         }
+    }
+
+    pub fn execute_block(
+        &mut self,
+        statements: Vec<Stmt>,
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<(), RoxError> {
+        let previous = std::mem::replace(&mut self.environment, environment);
+
+        let result = statements
+            .into_iter()
+            .try_for_each(|statement| self.execute(statement));
 
         self.environment = previous;
+        result
+    }
+
+    /// Runs `function` with a frame on the call stack for it.
+    fn call_function(
+        &mut self,
+        function: &RoxFunction,
+        arguments: &[Value],
+        paren: Option<&Token>,
+    ) -> Result<Value, RoxError> {
+        if self.frames.len() == FRAMES_MAX {
+            return Err(Self::call_error(paren, "Stack overflow."));
+        }
+        self.frames.push(StackFrame {
+            function: function.name().to_string(),
+            line: paren.map(|paren| paren.line),
+        });
+        let result = function
+            .call(self, arguments)
+            .map_err(|err| err.traced(|| self.trace()));
+        self.frames.pop();
+        result
+    }
+
+    /// The calls in progress, innermost first.
+    fn trace(&self) -> StackTrace {
+        Box::new(self.frames.iter().rev().cloned().collect())
     }
 
-    pub fn interpret(&mut self, statements: &Vec<Stmt>) {
-        for statement in statements {
-            self.execute(&statement);
+    /// Runs `statements` until the first runtime error, which is handed back for the host to
+    /// report against its source.
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RoxError> {
+        statements
+            .iter()
+            .try_for_each(|statement| self.execute(statement.clone()))
+    }
+
+    fn look_up_variable(&self, name: &Token, depth: Option<usize>) -> Result<Value, RoxError> {
+        match depth {
+            Some(distance) => Environment::get_at(&self.environment, distance, name),
+            None => self.globals.borrow().get(name),
         }
     }
+
+    fn number_operands(op: &Token, left: &Value, right: &Value) -> Result<(f64, f64), RoxError> {
+        match (left, right) {
+            (Value::Number(l), Value::Number(r)) => Ok((*l, *r)),
+            _ => Err(Self::runtime_error(op, "Operands must be numbers.")),
+        }
+    }
+
+    fn runtime_error(token: &Token, message: &str) -> RoxError {
+        RoxError::RuntimeError(token.clone(), message.to_string(), StackTrace::default())
+    }
 }
 
 impl StmtVisitor<Value> for Interpreter {
-    fn visit_if_stmt(&mut self, expr: Expr, then_stmt: Box<Stmt>, else_stmt: Option<Box<Stmt>>) {
-        let condition = self.evaluate(expr);
+    fn visit_if_stmt(
+        &mut self,
+        expr: Expr,
+        then_stmt: Box<Stmt>,
+        else_stmt: Option<Box<Stmt>>,
+    ) -> Result<(), RoxError> {
+        let condition = self.evaluate(expr)?;
         if self.is_truthy(condition) {
-            self.execute(&then_stmt);
-        } else if else_stmt.is_some() {
-            self.execute(&else_stmt.unwrap());
+            self.execute(*then_stmt)
+        } else if let Some(else_stmt) = else_stmt {
+            self.execute(*else_stmt)
+        } else {
+            Ok(())
         }
     }
 
-    fn visit_var_stmt(&mut self, token: Token, stmt_expr: Option<Expr>) {
-        let value: Value;
-
-        value = if let Some(stmt_expr) = stmt_expr {
-            self.evaluate(stmt_expr)
+    fn visit_var_stmt(&mut self, token: Token, stmt_expr: Option<Expr>) -> Result<(), RoxError> {
+        let value: Value = if let Some(stmt_expr) = stmt_expr {
+            self.evaluate(stmt_expr)?
         } else {
             Value::Nil
         };
 
-        self.environment.define(token.lexeme, value);
+        self.environment.borrow_mut().define(&token.lexeme, value);
+        Ok(())
     }
 
-    fn visit_function_stmt(&mut self, name: Token, params: Vec<Token>, body: Vec<Stmt>) {
+    fn visit_function_stmt(
+        &mut self,
+        name: Token,
+        params: Vec<Token>,
+        body: Vec<Stmt>,
+    ) -> Result<(), RoxError> {
         let function = RoxFunction::User {
             name: name.clone(),
-            params: params.clone(),
-            body: body.clone(),
+            params,
+            body: body.into(),
+            closure: Rc::clone(&self.environment),
+            is_initializer: false,
         };
         self.environment
-            .define(name.lexeme, Value::Callable(function));
+            .borrow_mut()
+            .define(&name.lexeme, Value::Callable(function));
+        Ok(())
     }
 
-    fn visit_expr_stmt(&mut self, stmt_expr: Expr) {
-        self.evaluate(stmt_expr);
+    fn visit_class_stmt(
+        &mut self,
+        name: Token,
+        superclass: Option<Expr>,
+        methods: Vec<Stmt>,
+    ) -> Result<(), RoxError> {
+        let superclass = match superclass {
+            Some(superclass_expr) => {
+                let superclass_name = match &superclass_expr {
+                    Expr::Var(superclass_name, _) => superclass_name.clone(),
+                    _ => name.clone(),
+                };
+                if superclass_name.lexeme == name.lexeme {
+                    return Err(Self::runtime_error(
+                        &superclass_name,
+                        "A class can't inherit from itself.",
+                    ));
+                }
+
+                match self.evaluate(superclass_expr)? {
+                    Value::Class(class) => Some(class),
+                    _ => {
+                        return Err(Self::runtime_error(
+                            &superclass_name,
+                            "Superclass must be a class.",
+                        ))
+                    }
+                }
+            }
+            None => None,
+        };
+
+        // Methods of a subclass close over a scope in which `super` is the superclass.
+        let mut method_environment = Rc::clone(&self.environment);
+        if let Some(superclass) = &superclass {
+            let mut super_environment = Environment::new(Some(method_environment));
+            super_environment.define("super", Value::Class(Rc::clone(superclass)));
+            method_environment = self.heap.environment(super_environment);
+        }
+
+        let mut class_methods = HashMap::new();
+        for method in methods {
+            if let Stmt::Function(method_name, params, body) = method {
+                let function = RoxFunction::User {
+                    name: method_name.clone(),
+                    params,
+                    body: body.into(),
+                    closure: Rc::clone(&method_environment),
+                    is_initializer: method_name.lexeme == "init",
+                };
+                class_methods.insert(method_name.lexeme, Method::Function(function));
+            }
+        }
+
+        let class = self.heap.class(RoxClass::new(
+            name.lexeme.clone(),
+            superclass,
+            class_methods,
+        ));
+        self.environment
+            .borrow_mut()
+            .define(&name.lexeme, Value::Class(class));
+        Ok(())
     }
 
-    fn visit_while_stmt(&mut self, expr: Expr, body: Box<Stmt>) {
-        let condition = self.evaluate(expr);
+    fn visit_expr_stmt(&mut self, stmt_expr: Expr) -> Result<(), RoxError> {
+        self.evaluate(stmt_expr)?;
+        Ok(())
+    }
 
-        while self.is_truthy(condition.clone()) {
-            self.execute(&body);
+    fn visit_while_stmt(
+        &mut self,
+        expr: Expr,
+        body: Box<Stmt>,
+        increment: Option<Expr>,
+    ) -> Result<(), RoxError> {
+        loop {
+            let condition = self.evaluate(expr.clone())?;
+            if !self.is_truthy(condition) {
+                return Ok(());
+            }
+            match self.execute(*body.clone()) {
+                Ok(()) | Err(RoxError::Continue) => {}
+                Err(RoxError::Break) => return Ok(()),
+                Err(err) => return Err(err),
+            }
+            if let Some(increment) = &increment {
+                self.evaluate(increment.clone())?;
+            }
         }
     }
 
-    fn visit_print_stmt(&mut self, stmt_expr: Expr) {
-        self.evaluate(stmt_expr);
+    // Like `return`, these unwind every block between them and their loop.
+    fn visit_break_stmt(&mut self, _keyword: Token) -> Result<(), RoxError> {
+        Err(RoxError::Break)
     }
 
-    fn visit_block_stmt(&mut self, statements: Vec<Stmt>) {
-        self.execute_block(statements, Environment::new(None));
+    fn visit_continue_stmt(&mut self, _keyword: Token) -> Result<(), RoxError> {
+        Err(RoxError::Continue)
+    }
+
+    fn visit_throw_stmt(&mut self, keyword: Token, value: Expr) -> Result<(), RoxError> {
+        let value = self.evaluate(value)?;
+        Err(RoxError::Throw(Box::new(value), keyword, self.trace()))
+    }
+
+    fn visit_try_stmt(
+        &mut self,
+        body: Vec<Stmt>,
+        catch: Option<(Token, Vec<Stmt>)>,
+        finally: Option<Vec<Stmt>>,
+    ) -> Result<(), RoxError> {
+        let result = match (self.visit_block_stmt(body), catch) {
+            (Err(err), Some((name, handler))) => {
+                match exception_value(err, &self.error_class, &mut self.heap) {
+                    Ok(exception) => {
+                        let mut environment = Environment::new(Some(Rc::clone(&self.environment)));
+                        environment.define(&name.lexeme, exception);
+                        let environment = self.heap.environment(environment);
+                        self.execute_block(handler, environment)
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            (result, _) => result,
+        };
+
+        // Runs however the rest finished, and an error or jump from in here replaces that.
+        if let Some(finally) = finally {
+            self.visit_block_stmt(finally)?;
+        }
+        result
+    }
+
+    fn visit_print_stmt(&mut self, keyword: Token, stmt_expr: Expr) -> Result<(), RoxError> {
+        let value = self.evaluate(stmt_expr)?;
+        self.output.print(&value).map_err(|err| {
+            Self::runtime_error(&keyword, &format!("Could not write output: {}", err))
+        })
+    }
+
+    fn visit_return_stmt(&mut self, _keyword: Token, value: Option<Expr>) -> Result<(), RoxError> {
+        let value = match value {
+            Some(value) => self.evaluate(value)?,
+            None => Value::Nil,
+        };
+
+        // Unwinds every enclosing block and loop back to `RoxFunction::call`.
+        Err(RoxError::Return(Box::new(value)))
+    }
+
+    fn visit_block_stmt(&mut self, statements: Vec<Stmt>) -> Result<(), RoxError> {
+        let environment = Environment::new(Some(Rc::clone(&self.environment)));
+        let environment = self.heap.environment(environment);
+        self.execute_block(statements, environment)
     }
 }
 
 impl ExprVisitor<Value> for Interpreter {
-    fn visit_logical_expr(&mut self, left: Box<Expr>, op: Token, right: Box<Expr>) -> Value {
-        let left = self.evaluate(*left);
+    fn visit_logical_expr(
+        &mut self,
+        left: Box<Expr>,
+        op: Token,
+        right: Box<Expr>,
+    ) -> Result<Value, RoxError> {
+        let left = self.evaluate(*left)?;
 
         if op.token_type == Or {
             if self.is_truthy(left.clone()) {
-                return left;
-            } else if !self.is_truthy(left.clone()) {
-                return left;
+                return Ok(left);
             }
+        } else if !self.is_truthy(left.clone()) {
+            return Ok(left);
         }
 
-        return self.evaluate(*right);
+        self.evaluate(*right)
     }
 
-    fn visit_assignment_expr(&mut self, name: Token, expr: Box<Expr>) -> Value {
-        let value = self.evaluate(*expr);
+    fn visit_assignment_expr(
+        &mut self,
+        name: Token,
+        expr: Box<Expr>,
+        depth: Option<usize>,
+    ) -> Result<Value, RoxError> {
+        let value = self.evaluate(*expr)?;
 
-        self.environment.assign(name, value.clone());
-        value
+        match depth {
+            Some(distance) => {
+                Environment::assign_at(&self.environment, distance, name, value.clone())
+            }
+            None => self.globals.borrow_mut().assign(name, value.clone()),
+        }?;
+        Ok(value)
     }
 
-    fn visit_var_expr(&mut self, name: Token) -> Value {
-        self.environment.get(&name).unwrap()
+    fn visit_var_expr(&mut self, name: Token, depth: Option<usize>) -> Result<Value, RoxError> {
+        self.look_up_variable(&name, depth)
     }
 
-    fn visit_literal_expr(&mut self, literal: Literal) -> Value {
-        literal.into()
+    fn visit_literal_expr(&mut self, literal: Literal) -> Result<Value, RoxError> {
+        Ok(literal.into())
     }
 
-    fn visit_grouping_expr(&mut self, group: Box<Expr>) -> Value {
+    fn visit_grouping_expr(&mut self, group: Box<Expr>) -> Result<Value, RoxError> {
         self.evaluate(*group)
     }
 
-    fn visit_unary_expr(&mut self, op: Token, right: Box<Expr>) -> Value {
-        let right = self.evaluate(*right);
+    fn visit_unary_expr(&mut self, op: Token, right: Box<Expr>) -> Result<Value, RoxError> {
+        let right = self.evaluate(*right)?;
 
         match op.token_type {
-            Minus => {
-                if let Value::Number(n) = right {
-                    return Value::Number(-n);
-                } else {
-                    panic!("{} must be a number", right);
-                }
-            }
-            Bang => {
-                return Value::Bool(!self.is_truthy(right));
-            }
+            Minus => match right {
+                Value::Number(n) => Ok(Value::Number(-n)),
+                _ => Err(Self::runtime_error(&op, "Operand must be a number.")),
+            },
+            Bang => Ok(Value::Bool(!self.is_truthy(right))),
 
-            _ => Value::Nil,
+            _ => Ok(Value::Nil),
         }
     }
 
-    fn visit_binary_expr(&mut self, left: Box<Expr>, op: Token, right: Box<Expr>) -> Value {
-        let left = self.evaluate(*left);
-        let right = self.evaluate(*right);
+    fn visit_binary_expr(
+        &mut self,
+        left: Box<Expr>,
+        op: Token,
+        right: Box<Expr>,
+    ) -> Result<Value, RoxError> {
+        let left = self.evaluate(*left)?;
+        let right = self.evaluate(*right)?;
 
         match op.token_type {
             Minus => {
-                if let (Value::Number(l), Value::Number(r)) = (left.clone(), right.clone()) {
-                    Value::Number(l - r)
-                } else {
-                    panic!("{} and {} must be numbers", left, right);
-                }
+                let (l, r) = Self::number_operands(&op, &left, &right)?;
+                Ok(Value::Number(l - r))
             }
             Slash => {
-                if let (Value::Number(l), Value::Number(r)) = (left.clone(), right.clone()) {
-                    Value::Number(l / r)
-                } else {
-                    panic!("{} and {} must be numbers", left, right);
-                }
+                let (l, r) = Self::number_operands(&op, &left, &right)?;
+                Ok(Value::Number(l / r))
             }
             Star => {
-                if let (Value::Number(l), Value::Number(r)) = (left.clone(), right.clone()) {
-                    Value::Number(l * r)
-                } else {
-                    panic!("{} and {} must be numbers", left, right);
-                }
+                let (l, r) = Self::number_operands(&op, &left, &right)?;
+                Ok(Value::Number(l * r))
             }
-            Plus => {
-                if let (Value::Number(l), Value::Number(r)) = (left.clone(), right.clone()) {
-                    Value::Number(l + r)
-                } else if let (Value::String_(l), Value::String_(r)) = (left.clone(), right.clone())
-                {
-                    Value::String_(l + &r)
-                } else {
-                    panic!(
-                        "{} and {} must both be numbers or both be strings",
-                        left, right
-                    );
+            Plus => match (left, right) {
+                (Value::Number(l), Value::Number(r)) => Ok(Value::Number(l + r)),
+                (Value::String_(l), Value::String_(r)) => {
+                    Ok(Value::String_([&*l, &*r].concat().into()))
                 }
-            }
+                _ => Err(Self::runtime_error(
+                    &op,
+                    "Operands must be two numbers or two strings.",
+                )),
+            },
             Greater => {
-                if let (Value::Number(l), Value::Number(r)) = (left.clone(), right.clone()) {
-                    Value::Bool(l > r)
-                } else {
-                    panic!("{} and {} must be numbers", left, right);
-                }
+                let (l, r) = Self::number_operands(&op, &left, &right)?;
+                Ok(Value::Bool(l > r))
             }
             GreaterEqual => {
-                if let (Value::Number(l), Value::Number(r)) = (left.clone(), right.clone()) {
-                    Value::Bool(l >= r)
-                } else {
-                    panic!("{} and {} must be numbers", left, right);
-                }
+                let (l, r) = Self::number_operands(&op, &left, &right)?;
+                Ok(Value::Bool(l >= r))
             }
             Less => {
-                if let (Value::Number(l), Value::Number(r)) = (left.clone(), right.clone()) {
-                    Value::Bool(l < r)
-                } else {
-                    panic!("{} and {} must be numbers", left, right);
-                }
+                let (l, r) = Self::number_operands(&op, &left, &right)?;
+                Ok(Value::Bool(l < r))
             }
             LessEqual => {
-                if let (Value::Number(l), Value::Number(r)) = (left.clone(), right.clone()) {
-                    Value::Bool(l <= r)
-                } else {
-                    panic!("{} and {} must be numbers", left, right);
-                }
+                let (l, r) = Self::number_operands(&op, &left, &right)?;
+                Ok(Value::Bool(l <= r))
             }
-            BangEqual => Value::Bool(!self.is_equal(left, right)),
-            EqualEqual => Value::Bool(self.is_equal(left, right)),
-            _ => Value::Nil,
+            BangEqual => Ok(Value::Bool(!self.is_equal(left, right))),
+            EqualEqual => Ok(Value::Bool(self.is_equal(left, right))),
+            _ => Ok(Value::Nil),
         }
     }
 
-    fn visit_call_expr(&mut self, callee: Box<Expr>, paren: Token, args: Vec<Expr>) -> Value {
-        let callee_value = self.evaluate(*callee);
+    fn visit_call_expr(
+        &mut self,
+        callee: Box<Expr>,
+        paren: Token,
+        args: Vec<Expr>,
+    ) -> Result<Value, RoxError> {
+        let callee_value = self.evaluate(*callee)?;
 
         let mut visited_args = Vec::new();
         for arg in args {
-            visited_args.push(self.evaluate(arg))
+            visited_args.push(self.evaluate(arg)?)
+        }
+
+        self.call(callee_value, &visited_args, Some(&paren))
+    }
+
+    fn visit_get_expr(&mut self, object: Box<Expr>, name: Token) -> Result<Value, RoxError> {
+        match self.evaluate(*object)? {
+            Value::Instance(instance) => RoxInstance::get(&instance, &name.lexeme, &mut self.heap)
+                .ok_or_else(|| {
+                    Self::runtime_error(&name, &format!("Undefined property '{}'.", name.lexeme))
+                }),
+            _ => Err(Self::runtime_error(
+                &name,
+                "Only instances have properties.",
+            )),
+        }
+    }
+
+    fn visit_set_expr(
+        &mut self,
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+    ) -> Result<Value, RoxError> {
+        match self.evaluate(*object)? {
+            Value::Instance(instance) => {
+                let value = self.evaluate(*value)?;
+                instance.borrow_mut().set(&name.lexeme, value.clone());
+                Ok(value)
+            }
+            _ => Err(Self::runtime_error(&name, "Only instances have fields.")),
         }
+    }
+
+    fn visit_list_expr(&mut self, _bracket: Token, elements: Vec<Expr>) -> Result<Value, RoxError> {
+        let mut values = Vec::with_capacity(elements.len());
+        for element in elements {
+            values.push(self.evaluate(element)?);
+        }
+        Ok(Value::List(self.heap.list(values)))
+    }
 
-        Value::Nil
+    fn visit_lambda_expr(
+        &mut self,
+        keyword: Token,
+        params: Vec<Token>,
+        body: Vec<Stmt>,
+    ) -> Result<Value, RoxError> {
+        Ok(Value::Callable(RoxFunction::User {
+            name: Token::new(Identifier, "lambda", None, keyword.line),
+            params,
+            body: body.into(),
+            closure: Rc::clone(&self.environment),
+            is_initializer: false,
+        }))
+    }
+
+    fn visit_map_expr(
+        &mut self,
+        brace: Token,
+        entries: Vec<(Expr, Expr)>,
+    ) -> Result<Value, RoxError> {
+        let mut map = Map::new();
+        for (key, value) in entries {
+            let key = self.evaluate(key)?;
+            let key =
+                Key::try_from(&key).map_err(|message| Self::runtime_error(&brace, &message))?;
+            map.insert(key, self.evaluate(value)?);
+        }
+        Ok(Value::Map(self.heap.map(map)))
+    }
+
+    fn visit_index_expr(
+        &mut self,
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    ) -> Result<Value, RoxError> {
+        let object = self.evaluate(*object)?;
+        let index = self.evaluate(*index)?;
+        object
+            .get_index(&index)
+            .map_err(|message| Self::runtime_error(&bracket, &message))
+    }
+
+    fn visit_set_index_expr(
+        &mut self,
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    ) -> Result<Value, RoxError> {
+        let object = self.evaluate(*object)?;
+        let index = self.evaluate(*index)?;
+        let value = self.evaluate(*value)?;
+        object
+            .set_index(&index, value.clone())
+            .map_err(|message| Self::runtime_error(&bracket, &message))?;
+        Ok(value)
+    }
+
+    fn visit_this_expr(&mut self, keyword: Token, depth: Option<usize>) -> Result<Value, RoxError> {
+        self.look_up_variable(&keyword, depth)
+    }
+
+    fn visit_super_expr(
+        &mut self,
+        keyword: Token,
+        method: Token,
+        depth: Option<usize>,
+    ) -> Result<Value, RoxError> {
+        let distance = depth.expect("'super' is always resolved to a local scope.");
+        let superclass = match Environment::get_at(&self.environment, distance, &keyword)? {
+            Value::Class(superclass) => superclass,
+            _ => {
+                return Err(Self::runtime_error(
+                    &keyword,
+                    "Can't use 'super' in a class with no superclass.",
+                ))
+            }
+        };
+        // `this` is bound in the scope just inside the one holding `super`.
+        let this = Token::new(This, "this", None, keyword.line);
+        let object = Environment::get_at(&self.environment, distance - 1, &this)?;
+
+        match superclass.find_method(&method.lexeme) {
+            Some(method) => Ok(method.bind(object, &mut self.heap)),
+            None => Err(Self::runtime_error(
+                &method,
+                &format!("Undefined property '{}'.", method.lexeme),
+            )),
+        }
     }
 
     fn is_truthy(&mut self, value: Value) -> bool {
@@ -254,13 +675,7 @@ impl ExprVisitor<Value> for Interpreter {
     }
 
     fn is_equal(&mut self, a: Value, b: Value) -> bool {
-        match (a, b) {
-            (Value::Nil, Value::Nil) => true,
-            (Value::Bool(a), Value::Bool(b)) => a == b,
-            (Value::String_(a), Value::String_(b)) => a == b,
-            (Value::Number(a), Value::Number(b)) => (a - b).abs() < f64::EPSILON,
-            _ => false,
-        }
+        a.equals(&b)
     }
 }
 
@@ -269,17 +684,49 @@ mod tests {
     use super::*;
     use crate::ast;
     use crate::error::RoxError;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use crate::scanner::Scanner;
     use crate::token::Literal;
-    use crate::token::TokenType::Var;
+    use crate::token::TokenType::{Identifier, Print, Var};
+
+    fn try_interpret_source(source: &str) -> (Interpreter, Result<(), RoxError>) {
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let (mut statements, errors) = Parser::new(tokens).parse();
+        assert!(errors.is_empty());
+        Resolver::new().resolve(&mut statements).unwrap();
+        let mut interpreter = Interpreter::new();
+        let result = interpreter.interpret(&statements);
+        (interpreter, result)
+    }
+
+    fn interpret_source(source: &str) -> Interpreter {
+        let (interpreter, result) = try_interpret_source(source);
+        result.unwrap();
+        interpreter
+    }
+
+    fn runtime_error_message(source: &str) -> String {
+        try_interpret_source(source).1.unwrap_err().to_string()
+    }
+
+    fn global(interpreter: &Interpreter, name: &str) -> Value {
+        interpreter
+            .globals
+            .borrow()
+            .get(&Token::new(Identifier, name, None, 1))
+            .unwrap()
+    }
 
     #[test]
     fn test_interpret_print_statement() -> Result<(), RoxError> {
         let mut interpreter = Interpreter::new();
-        let statements = vec![ast::Stmt::Print(ast::Expr::Literal(Literal::String_(
-            "one".to_string(),
-        )))];
+        let statements = vec![ast::Stmt::Print(
+            Token::new(Print, "print", None, 1),
+            ast::Expr::Literal(Literal::String_("one".into())),
+        )];
 
-        interpreter.interpret(&statements);
+        interpreter.interpret(&statements)?;
         Ok(())
     }
 
@@ -288,9 +735,448 @@ mod tests {
         let mut interpreter = Interpreter::new();
         let statements = vec![ast::Stmt::Var(
             Token::new(Var, "a", None, 1),
-            Some(ast::Expr::Literal(Literal::String_("one".to_string()))),
+            Some(ast::Expr::Literal(Literal::String_("one".into()))),
         )];
-        interpreter.interpret(&statements);
+        interpreter.interpret(&statements)?;
         Ok(())
     }
+
+    #[test]
+    fn test_interpret_class() {
+        let interpreter = interpret_source(
+            "class Counter {
+                init(start) { this.count = start; }
+                increment() { this.count = this.count + 1; }
+            }
+            var counter = Counter(5);
+            counter.increment();
+            var count = counter.count;
+            var reinitialized = counter.init(1);",
+        );
+
+        assert_eq!(global(&interpreter, "count"), Value::Number(6.0));
+        assert_eq!(global(&interpreter, "Counter").to_string(), "Counter");
+        assert_eq!(
+            global(&interpreter, "counter").to_string(),
+            "Counter instance"
+        );
+        assert_eq!(
+            global(&interpreter, "reinitialized"),
+            global(&interpreter, "counter")
+        );
+    }
+
+    #[test]
+    fn test_interpret_identity_equality() {
+        let interpreter = interpret_source(
+            "class A {}
+            var f = A;
+            var same_class = f == A;
+            var other_class = A == fun () {};
+            var a = A();
+            var same_instance = a == a;
+            var other_instance = a == A();
+            fun g() {}
+            fun h() {}
+            var same_function = g == g;
+            var other_function = g == h;
+            var same_native = clock == clock;",
+        );
+
+        for (name, expected) in [
+            ("same_class", true),
+            ("other_class", false),
+            ("same_instance", true),
+            ("other_instance", false),
+            ("same_function", true),
+            ("other_function", false),
+            ("same_native", true),
+        ] {
+            assert_eq!(
+                global(&interpreter, name),
+                Value::Bool(expected),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_interpret_bound_method() {
+        let interpreter = interpret_source(
+            "class Person {
+                greet() { this.greeted = true; }
+            }
+            var person = Person();
+            var greet = person.greet;
+            greet();
+            var greeted = person.greeted;",
+        );
+
+        assert_eq!(global(&interpreter, "greeted"), Value::Bool(true));
+    }
+
+    #[test]
+    fn test_interpret_inheritance() {
+        let interpreter = interpret_source(
+            "class Animal {
+                init(name) { this.name = name; }
+                speak() { this.sound = \"...\"; }
+                describe() { this.described = true; }
+            }
+            class Dog < Animal {
+                init(name) { super.init(name); this.tricks = 0; }
+                speak() { super.speak(); this.sound = this.sound + \"woof\"; }
+            }
+            var dog = Dog(\"Rex\");
+            dog.speak();
+            dog.describe();
+            var name = dog.name;
+            var sound = dog.sound;
+            var described = dog.described;",
+        );
+
+        assert_eq!(global(&interpreter, "name"), Value::String_("Rex".into()));
+        assert_eq!(
+            global(&interpreter, "sound"),
+            Value::String_("...woof".into())
+        );
+        assert_eq!(global(&interpreter, "described"), Value::Bool(true));
+    }
+
+    #[test]
+    fn test_interpret_inherit_from_non_class() {
+        assert_eq!(
+            runtime_error_message("var NotAClass = \"nope\";\nclass A < NotAClass {}"),
+            "Superclass must be a class.\n[line 2]"
+        );
+    }
+
+    #[test]
+    fn test_interpret_inherit_from_itself() {
+        assert_eq!(
+            runtime_error_message("class A {}\n\nclass A < A {}"),
+            "A class can't inherit from itself.\n[line 3]"
+        );
+    }
+
+    #[test]
+    fn test_interpret_return_unwinds_blocks_and_loops() {
+        let interpreter = interpret_source(
+            "var scope = \"global\";
+            fun find(limit) {
+                var i = 0;
+                while (true) {
+                    {
+                        var scope = \"loop\";
+                        if (i == limit) return i * 10;
+                    }
+                    i = i + 1;
+                }
+            }
+            fun nothing() { return; }
+            var found = find(3);
+            var empty = nothing();
+            var after = scope;",
+        );
+
+        assert_eq!(global(&interpreter, "found"), Value::Number(30.0));
+        assert_eq!(global(&interpreter, "empty"), Value::Nil);
+        assert_eq!(
+            global(&interpreter, "after"),
+            Value::String_("global".into())
+        );
+    }
+
+    #[test]
+    fn test_interpret_return_from_initializer() {
+        let interpreter = interpret_source(
+            "class A {
+                init() { this.ready = true; return; }
+            }
+            var a = A();
+            var ready = a.ready;",
+        );
+
+        assert_eq!(global(&interpreter, "ready"), Value::Bool(true));
+    }
+
+    #[test]
+    fn test_interpret_closure_counter() {
+        let interpreter = interpret_source(
+            "fun makeCounter() {
+                var i = 0;
+                fun count() {
+                    i = i + 1;
+                    return i;
+                }
+                return count;
+            }
+            var counter = makeCounter();
+            var other = makeCounter();
+            var first = counter();
+            var second = counter();
+            var independent = other();",
+        );
+
+        assert_eq!(global(&interpreter, "first"), Value::Number(1.0));
+        assert_eq!(global(&interpreter, "second"), Value::Number(2.0));
+        assert_eq!(global(&interpreter, "independent"), Value::Number(1.0));
+    }
+
+    #[test]
+    fn test_interpret_recursion_and_global_mutation() {
+        let interpreter = interpret_source(
+            "var calls = 0;
+            fun fib(n) {
+                calls = calls + 1;
+                if (n < 2) return n;
+                return fib(n - 1) + fib(n - 2);
+            }
+            var result = fib(10);",
+        );
+
+        assert_eq!(global(&interpreter, "result"), Value::Number(55.0));
+        assert_eq!(global(&interpreter, "calls"), Value::Number(177.0));
+    }
+
+    #[test]
+    fn test_interpret_closure_binds_resolved_scope() {
+        let interpreter = interpret_source(
+            "var a = \"global\";
+            var first;
+            var second;
+            {
+                fun showA() { return a; }
+                first = showA();
+                var a = \"block\";
+                second = showA();
+            }",
+        );
+
+        assert_eq!(
+            global(&interpreter, "first"),
+            Value::String_("global".into())
+        );
+        assert_eq!(
+            global(&interpreter, "second"),
+            Value::String_("global".into())
+        );
+    }
+
+    #[test]
+    fn test_interpret_runtime_errors() {
+        assert_eq!(
+            runtime_error_message("print -\"a\";"),
+            "Operand must be a number.\n[line 1]"
+        );
+        assert_eq!(
+            runtime_error_message("var a = 1;\nprint a * \"b\";"),
+            "Operands must be numbers.\n[line 2]"
+        );
+        assert_eq!(
+            runtime_error_message("print 1 + nil;"),
+            "Operands must be two numbers or two strings.\n[line 1]"
+        );
+        assert_eq!(
+            runtime_error_message("print missing;"),
+            "Undefined variable 'missing'."
+        );
+        assert_eq!(
+            runtime_error_message("\"not a function\"();"),
+            "Can only call functions and classes.\n[line 1]"
+        );
+        assert_eq!(
+            runtime_error_message("fun add(a, b) { return a + b; }\nadd(1,\n  2, 3);"),
+            "Expected 2 arguments but got 3.\n[line 3]"
+        );
+        assert_eq!(
+            runtime_error_message("class A { init(x) {} }\nA();"),
+            "Expected 1 arguments but got 0.\n[line 2]"
+        );
+        assert_eq!(
+            runtime_error_message("class A {}\nA(1);"),
+            "Expected 0 arguments but got 1.\n[line 2]"
+        );
+    }
+
+    #[test]
+    fn test_interpret_stops_at_runtime_error() {
+        let (interpreter, result) = try_interpret_source("var a = 1;\na = a - \"x\";\na = 3;");
+
+        assert!(matches!(result, Err(RoxError::RuntimeError(token, ..)) if token.line == 2));
+        assert_eq!(global(&interpreter, "a"), Value::Number(1.0));
+    }
+
+    #[test]
+    fn test_interpret_break_and_continue() {
+        let interpreter = interpret_source(
+            "var total = 0;
+            for (var i = 0; i < 10; i = i + 1) {
+                if (i == 1) continue;
+                { if (i == 4) break; }
+                total = total + i;
+            }
+            var n = 0;
+            while (true) { n = n + 1; if (n < 5) continue; break; }",
+        );
+
+        assert_eq!(global(&interpreter, "total"), Value::Number(5.0));
+        assert_eq!(global(&interpreter, "n"), Value::Number(5.0));
+    }
+
+    #[test]
+    fn test_interpret_exceptions() {
+        let interpreter = interpret_source(
+            "var message; var line; var thrown; var log = \"\";
+            try { 1 + nil; } catch (e) { message = e.message; line = e.line; }
+            try { throw [1]; } catch (e) { thrown = e; } finally { log = log + \"a\"; }
+            fun f() { try { return \"r\"; } finally { log = log + \"b\"; } }
+            var r = f(); log = log + r;
+            try { try { missing; } finally { log = log + \"c\"; } } catch (e) { log = log + \"d\"; }",
+        );
+
+        assert_eq!(
+            global(&interpreter, "message"),
+            Value::from("Operands must be two numbers or two strings.")
+        );
+        assert_eq!(global(&interpreter, "line"), Value::Number(2.0));
+        assert_eq!(global(&interpreter, "thrown").to_string(), "[1]");
+        assert_eq!(global(&interpreter, "log"), Value::from("abrcd"));
+
+        let (_, result) = try_interpret_source(
+            "fun fail(x) {\n  throw x;\n}\nfun call() { fail(\"oops\"); }\n\ncall();",
+        );
+        let err = result.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Uncaught exception: oops\n[line 2]\n  at fail (line 4)\n  at call (line 6)"
+        );
+        assert!(err.is_runtime());
+    }
+
+    #[test]
+    fn test_interpret_stack_trace() {
+        let message = runtime_error_message(
+            "fun fib(n) {\n  if (n < 2) return n + nil;\n  return fib(n - 1) + fib(n - 2);\n}\nfib(2);",
+        );
+        assert_eq!(
+            message,
+            "Operands must be two numbers or two strings.\n[line 2]\n  \
+             at fib (line 3)\n  at fib (line 5)"
+        );
+
+        let (_, result) = try_interpret_source("var f = fun () { return missing; };\nf();");
+        assert!(matches!(
+            result,
+            Err(RoxError::UndefinedVariableError(_, trace)) if trace.len() == 1
+        ));
+
+        // Each Lox call nests several Rust frames, more than a test thread's default stack holds.
+        let message = std::thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(|| runtime_error_message("fun f() { f(); } f();"))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(message.starts_with("Stack overflow.\n[line 1]\n  at f (line 1)\n"));
+        assert!(message.contains("[previous frame repeated 253 more times]"));
+    }
+
+    #[test]
+    fn test_interpret_lambdas() {
+        let interpreter = interpret_source(
+            "fun twice(f, x) { return f(f(x)); }
+            var step = 3;
+            var a = twice(fun (n) { return n + step; }, 1);
+            var b = fun () {};
+            var c = 0;
+            fun (n) { c = n; }(7);",
+        );
+
+        assert_eq!(global(&interpreter, "a"), Value::Number(7.0));
+        assert_eq!(global(&interpreter, "b").to_string(), "<fn lambda>");
+        assert_eq!(global(&interpreter, "c"), Value::Number(7.0));
+    }
+
+    #[test]
+    fn test_lists() {
+        let interpreter = interpret_source(
+            "var a = [1, [2]];
+            a[1][0] = a[0] + 1;
+            push(a, \"x\");
+            var b = a[1][0] + len(a);",
+        );
+
+        assert_eq!(global(&interpreter, "a").to_string(), "[1, [2], \"x\"]");
+        assert_eq!(global(&interpreter, "b"), Value::Number(5.0));
+        assert_eq!(
+            runtime_error_message("var a = [];\nprint a[\n0];"),
+            "List index out of range.\n[line 3]"
+        );
+        assert_eq!(
+            runtime_error_message("var a = [1];\na[\"0\"] = 1;"),
+            "List index must be an integer.\n[line 2]"
+        );
+        assert_eq!(
+            runtime_error_message("nil[0];"),
+            "Only lists and maps can be indexed.\n[line 1]"
+        );
+    }
+
+    #[test]
+    fn test_maps() {
+        let interpreter = interpret_source(
+            "var m = {\"a\": 1, 2: [true]};
+            m[\"b\"] = m[\"a\"] + len(m[2]);
+            delete(m, 2);
+            m[nil] = len(m);
+            var k = keys(m);
+            var h = has(m, \"a\");",
+        );
+
+        assert_eq!(
+            global(&interpreter, "m").to_string(),
+            "{\"a\": 1, \"b\": 2, nil: 2}"
+        );
+        assert_eq!(global(&interpreter, "k").to_string(), "[\"a\", \"b\", nil]");
+        assert_eq!(global(&interpreter, "h"), Value::Bool(true));
+        assert_eq!(
+            runtime_error_message("var m = {};\nprint m[\n\"a\"];"),
+            "Undefined key \"a\".\n[line 3]"
+        );
+        assert_eq!(
+            runtime_error_message("var m = {[]:\n1};"),
+            "Only strings, numbers, booleans and nil can be map keys.\n[line 2]"
+        );
+    }
+
+    #[test]
+    fn test_define_native() {
+        let tokens =
+            Scanner::new("var a = sum(1, 2, 3);\nsum(1, \"x\");\nsum();".to_string()).scan_tokens();
+        let (mut statements, _) = Parser::new(tokens).parse();
+        Resolver::new().resolve(&mut statements).unwrap();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.define_native("sum", Arity::Variadic(1), |arguments| {
+            let mut total = 0.0;
+            for argument in arguments {
+                total += f64::try_from(argument.clone())?;
+            }
+            Ok(total.into())
+        });
+
+        let err = interpreter.interpret(&statements).unwrap_err();
+        assert_eq!(global(&interpreter, "a"), Value::Number(6.0));
+        assert_eq!(
+            err.to_string(),
+            "Expected a number but got \"x\".\n[line 2]"
+        );
+        let err = interpreter.interpret(&statements[2..]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Expected at least 1 arguments but got 0.\n[line 3]"
+        );
+    }
 }
@@ -0,0 +1,535 @@
+//! A Lox implementation, for running scripts from Rust.
+//!
+//! `Rox` is a session: globals defined by one `eval_str` stay around for the next, and the
+//! host can read and write them or call the functions a script defined.
+//!
+//! ```
+//! use rox::Rox;
+//!
+//! let mut rox = Rox::new();
+//! rox.eval_str("fun add(a, b) { return a + b; }").unwrap();
+//! let sum = rox.call_function("add", &[1.0.into(), 2.0.into()]).unwrap();
+//! assert_eq!(sum, 3.0.into());
+//! ```
+//!
+//! The `rox` command line tool and its REPL are behind the default `cli` feature. Depending
+//! on rox with `default-features = false` leaves out their dependencies.
+
+pub mod ast;
+pub mod callable;
+pub mod chunk;
+pub mod class;
+pub mod compiler;
+pub mod dump;
+pub mod environment;
+pub mod error;
+pub mod function;
+pub mod gc;
+pub mod intern;
+pub mod interpreter;
+pub mod list;
+pub mod map;
+pub mod native;
+pub mod output;
+pub mod parser;
+pub mod resolver;
+pub mod scanner;
+pub mod token;
+pub mod vm;
+
+pub use ast::Value;
+pub use error::{RoxError, StackFrame, StackTrace};
+pub use native::Arity;
+pub use output::Capture;
+
+use ast::{Expr, ExprVisitor, Stmt};
+use compiler::Compiler;
+use gc::Heap;
+use interpreter::Interpreter;
+use output::Output;
+use parser::Parser;
+use resolver::Resolver;
+use scanner::Scanner;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+use token::Token;
+use token::TokenType::Identifier;
+use vm::Vm;
+
+/// Which engine runs a script once it has been parsed and resolved.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Backend {
+    /// Walk the syntax tree directly.
+    #[default]
+    Interpreter,
+    /// Compile to bytecode and run it on the `vm`.
+    Vm,
+}
+
+enum Engine {
+    Interpreter(Interpreter),
+    Vm(Vm),
+}
+
+pub struct Rox {
+    engine: Engine,
+}
+
+impl Default for Rox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rox {
+    pub fn new() -> Self {
+        Self::with_backend(Backend::default())
+    }
+
+    pub fn with_backend(backend: Backend) -> Self {
+        let engine = match backend {
+            Backend::Interpreter => Engine::Interpreter(Interpreter::new()),
+            Backend::Vm => Engine::Vm(Vm::new()),
+        };
+        Self { engine }
+    }
+
+    /// Runs a script. A script with static errors doesn't run at all, and all of them are
+    /// returned; otherwise the error is the runtime error that stopped it.
+    pub fn eval_str(&mut self, source: &str) -> Result<(), Vec<RoxError>> {
+        let statements = parse(source)?;
+        self.run(&statements)
+    }
+
+    /// Runs a line of interactive input. It runs as a script, except that a lone expression
+    /// may leave off its `;`, and has its value returned instead of discarded.
+    pub fn eval_line(&mut self, source: &str) -> Result<Option<Value>, Vec<RoxError>> {
+        let statements = parse(source).or_else(|errors| {
+            // Keep the errors from the input as written if adding the `;` doesn't help.
+            parse(&format!("{};", source.trim_end())).map_err(|_| errors)
+        })?;
+        match statements.as_slice() {
+            [Stmt::Expression(expr)] => self.evaluate(expr).map(Some).map_err(|err| vec![err]),
+            _ => self.run(&statements).map(|()| None),
+        }
+    }
+
+    fn run(&mut self, statements: &[Stmt]) -> Result<(), Vec<RoxError>> {
+        let result = match &mut self.engine {
+            Engine::Interpreter(interpreter) => interpreter.interpret(statements),
+            Engine::Vm(vm) => {
+                let script = Compiler::new()
+                    .compile(statements)
+                    .map_err(|err| vec![err])?;
+                vm.interpret(script)
+            }
+        };
+        result.map_err(|err| vec![err])
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value, RoxError> {
+        match &mut self.engine {
+            Engine::Interpreter(interpreter) => interpreter.evaluate(expr.clone()),
+            Engine::Vm(vm) => vm.evaluate(Compiler::new().compile_expression(expr)?),
+        }
+    }
+
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> Result<(), Vec<RoxError>> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|err| {
+            vec![RoxError::IoError(format!(
+                "Could not read '{}': {}",
+                path.display(),
+                err
+            ))]
+        })?;
+        self.eval_str(&source)
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        match &self.engine {
+            Engine::Interpreter(interpreter) => {
+                interpreter.globals.borrow().get(&global_token(name)).ok()
+            }
+            Engine::Vm(vm) => vm.global(name),
+        }
+    }
+
+    /// Every global, sorted by name, natives included.
+    pub fn globals(&self) -> Vec<(Rc<str>, Value)> {
+        match &self.engine {
+            Engine::Interpreter(interpreter) => interpreter.globals.borrow().bindings(),
+            Engine::Vm(vm) => vm.globals(),
+        }
+    }
+
+    pub fn set_global(&mut self, name: &str, value: impl Into<Value>) {
+        let value = value.into();
+        self.heap().adopt(&value);
+        match &mut self.engine {
+            Engine::Interpreter(interpreter) => {
+                interpreter.globals.borrow_mut().define(name, value)
+            }
+            Engine::Vm(vm) => vm.set_global(name, value),
+        }
+    }
+
+    /// Calls the global function or class `name`. If the call can't be made at all, as when
+    /// `name` isn't defined, the error is a `HostCallError`, which has no line.
+    pub fn call_function(&mut self, name: &str, arguments: &[Value]) -> Result<Value, RoxError> {
+        let callee = self
+            .get_global(name)
+            .ok_or_else(|| RoxError::HostCallError(format!("Undefined variable '{}'.", name)))?;
+        match &mut self.engine {
+            Engine::Interpreter(interpreter) => interpreter.call(callee, arguments, None),
+            Engine::Vm(vm) => vm.call(callee, arguments),
+        }
+    }
+
+    /// Defines a global function written in Rust. See `Interpreter::define_native`.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        body: impl Fn(&[Value]) -> Result<Value, RoxError> + 'static,
+    ) {
+        match &mut self.engine {
+            Engine::Interpreter(interpreter) => interpreter.define_native(name, arity, body),
+            Engine::Vm(vm) => vm.define_native(name, arity, body),
+        }
+    }
+
+    /// Sends what `print` statements write to `writer` instead of stdout.
+    pub fn set_output(&mut self, writer: impl Write + 'static) {
+        let output = Output::new(Box::new(writer));
+        match &mut self.engine {
+            Engine::Interpreter(interpreter) => interpreter.output = output,
+            Engine::Vm(vm) => vm.output = output,
+        }
+    }
+
+    /// The garbage collector behind this session's objects.
+    pub fn heap(&mut self) -> &mut Heap {
+        match &mut self.engine {
+            Engine::Interpreter(interpreter) => &mut interpreter.heap,
+            Engine::Vm(vm) => &mut vm.heap,
+        }
+    }
+}
+
+/// Scans, parses and resolves a script, collecting every error those stages find.
+pub fn parse(source: &str) -> Result<Vec<Stmt>, Vec<RoxError>> {
+    let mut scanner = Scanner::new(source.to_string());
+    let tokens = scanner.scan_tokens();
+
+    let (mut statements, mut errors) = Parser::new(tokens).parse();
+    errors.splice(0..0, scanner.errors);
+    if errors.is_empty() {
+        if let Err(resolve_errors) = Resolver::new().resolve(&mut statements) {
+            errors = resolve_errors;
+        }
+    }
+    if errors.is_empty() {
+        Ok(statements)
+    } else {
+        Err(errors)
+    }
+}
+
+fn global_token(name: &str) -> Token {
+    Token::new(Identifier, name, None, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sessions() -> [Rox; 2] {
+        [
+            Rox::with_backend(Backend::Interpreter),
+            Rox::with_backend(Backend::Vm),
+        ]
+    }
+
+    #[test]
+    fn test_globals_persist_between_evals() {
+        for mut rox in sessions() {
+            rox.set_global("base", 10.0);
+            rox.eval_str("var total = base;").unwrap();
+            rox.eval_str("total = total + 1;").unwrap();
+
+            assert_eq!(rox.get_global("total"), Some(Value::Number(11.0)));
+            assert_eq!(rox.get_global("missing"), None);
+        }
+    }
+
+    #[test]
+    fn test_eval_line() {
+        for mut rox in sessions() {
+            assert_eq!(rox.eval_line("var a = 2;").unwrap(), None);
+            assert_eq!(rox.eval_line("a * 3").unwrap(), Some(Value::Number(6.0)));
+            assert_eq!(rox.eval_line("a + 1;\n").unwrap(), Some(Value::Number(3.0)));
+            assert_eq!(rox.eval_line("{ a = 5; }").unwrap(), None);
+            assert_eq!(rox.get_global("a"), Some(Value::Number(5.0)));
+
+            let errors = rox.eval_line("print (1").unwrap_err();
+            assert!(matches!(
+                &errors[..],
+                [RoxError::ParseError(_, message)] if message == "Expect ')' after expression."
+            ));
+            assert!(rox.eval_line("-nil").unwrap_err()[0].is_runtime());
+        }
+    }
+
+    #[test]
+    fn test_call_function() {
+        for mut rox in sessions() {
+            rox.eval_str("fun greet(name) { return \"hi \" + name; }")
+                .unwrap();
+
+            assert_eq!(
+                rox.call_function("greet", &["bob".into()]).unwrap(),
+                Value::from("hi bob")
+            );
+            assert_eq!(
+                rox.call_function("clock", &[1.0.into()])
+                    .unwrap_err()
+                    .to_string(),
+                "Expected 0 arguments but got 1."
+            );
+            assert_eq!(
+                rox.call_function("greet", &[]).unwrap_err().to_string(),
+                "Expected 1 arguments but got 0."
+            );
+            assert_eq!(
+                rox.call_function("greet", &[1.0.into()])
+                    .unwrap_err()
+                    .to_string(),
+                "Operands must be two numbers or two strings.\n[line 1]\n  \
+                 at greet (called by the host)"
+            );
+            assert!(matches!(
+                rox.call_function("missing", &[]),
+                Err(RoxError::HostCallError(message)) if message == "Undefined variable 'missing'."
+            ));
+        }
+    }
+
+    #[test]
+    fn test_error_trace() {
+        for mut rox in sessions() {
+            rox.eval_str("fun check(n) {\n  return n < 0;\n}\nfun run(n) { return check(n); }")
+                .unwrap();
+
+            let err = rox.call_function("run", &["x".into()]).unwrap_err();
+            let trace: Vec<(&str, Option<usize>)> = err
+                .trace()
+                .iter()
+                .map(|frame| (frame.function.as_str(), frame.line))
+                .collect();
+            // The call from the host has no line to point at.
+            assert_eq!(trace, vec![("check", Some(4)), ("run", None)]);
+        }
+    }
+
+    #[test]
+    fn test_number_equality_is_exact() {
+        for mut rox in sessions() {
+            rox.eval_str("var tiny = 0.00000000000000001 == 0; var sum = 0.1 + 0.2 == 0.3; var same = 0.5 == 1 / 2;")
+                .unwrap();
+
+            assert_eq!(rox.get_global("tiny"), Some(Value::Bool(false)));
+            assert_eq!(rox.get_global("sum"), Some(Value::Bool(false)));
+            assert_eq!(rox.get_global("same"), Some(Value::Bool(true)));
+        }
+    }
+
+    #[test]
+    fn test_runtime_errors_render_alike() {
+        for source in [
+            "var a = 1;\nprint a * \"b\";",
+            "print missing;",
+            "fun f(a) {}\nf();",
+            "fun inner(x) {\n  return -x;\n}\ninner(\"a\");",
+        ] {
+            let handler = error::ErrorHandler::new(source);
+            let [interpreted, compiled] = sessions().map(|mut rox| {
+                let errors = rox.eval_str(source).unwrap_err();
+                handler.render(&errors[0])
+            });
+
+            assert!(interpreted.contains('^'), "{}", interpreted);
+            assert_eq!(interpreted, compiled);
+        }
+    }
+
+    #[test]
+    fn test_static_errors_reject_alike() {
+        for source in ["print (1;\nvar = 2;", "return 1;", "{ var a = a; }"] {
+            let handler = error::ErrorHandler::new(source);
+            let [interpreted, compiled] = sessions().map(|mut rox| {
+                let errors = rox.eval_str(source).unwrap_err();
+                errors
+                    .iter()
+                    .map(|err| handler.render(err))
+                    .collect::<Vec<_>>()
+            });
+
+            assert_eq!(interpreted, compiled);
+        }
+    }
+
+    /// Runs `source` on both backends, returning what each printed followed by its error, if
+    /// it stopped with one, as rendered for the command line.
+    fn run_alike(source: &str) -> [String; 2] {
+        let handler = error::ErrorHandler::new(source);
+        sessions().map(|mut rox| {
+            let capture = Capture::new();
+            rox.set_output(capture.clone());
+            let errors = rox.eval_str(source).err().unwrap_or_default();
+            let mut output = capture.contents();
+            for err in errors {
+                output.push_str(&handler.render(&err));
+            }
+            output
+        })
+    }
+
+    #[test]
+    fn test_classes_run_alike() {
+        let [interpreted, compiled] = run_alike(
+            "class A {
+              init(x) { this.x = x; }
+              name() { return \"a\"; }
+              describe() { return this.name(); }
+            }
+            class B < A {
+              init(x) { super.init(x * 2); }
+              name() { return \"b\" + super.name(); }
+            }
+            var b = B(1);
+            var describe = b.describe;
+            print describe();
+            print b.x;
+            print b.init(3) == b;
+            print b.x;
+            print describe;
+            print B;
+            print b;
+            { class Local { again() { return Local(); } } print Local().again(); }
+            print b.missing;",
+        );
+
+        assert_eq!(
+            interpreted,
+            "ba\n2\ntrue\n6\n<fn describe>\nB\nB instance\nLocal instance\n\
+             Undefined property 'missing'.\n[line 20]\n   |\n20 |             print b.missing;\n   \
+             |                     ^^^^^^^"
+        );
+        assert_eq!(interpreted, compiled);
+
+        for source in [
+            "var a = 1;\nclass A < a {}",
+            "print 1;\nclass A < A {}",
+            "class A { init(x) {} }\nA();",
+            "var a = 1;\nprint a.b;",
+            "var a = 1;\na.b = 2;",
+        ] {
+            let [interpreted, compiled] = run_alike(source);
+            assert!(interpreted.contains('^'), "{}", interpreted);
+            assert_eq!(interpreted, compiled);
+        }
+    }
+
+    #[test]
+    fn test_exceptions_run_alike() {
+        let [interpreted, compiled] = run_alike(
+            "try { 1 + nil; } catch (e) { print e.message; print e.line; }
+            try { throw [1]; } catch (e) { print e; } finally { print \"a\"; }
+            fun f() { try { return \"r\"; } finally { print \"b\"; } }
+            print f();
+            for (var i = 0; i < 3; i = i + 1) {
+              try { if (i == 0) continue; break; } finally { print i; }
+            }
+            try { try { missing; } finally { print \"c\"; } } catch (e) { print e.message; }
+            fun g() { try { throw 1; } finally { return 2; } }
+            print g();
+            fun fail(x) {
+              throw x;
+            }
+            try { fail(\"d\"); } finally { print \"e\"; }",
+        );
+
+        assert_eq!(
+            interpreted,
+            "Operands must be two numbers or two strings.\n1\n[1]\na\nb\nr\n0\n1\nc\n\
+             Undefined variable 'missing'.\n2\ne\nUncaught exception: d\n[line 12]\n   |\n\
+             12 |               throw x;\n   |               ^^^^^\n  at fail (line 14)"
+        );
+        assert_eq!(interpreted, compiled);
+    }
+
+    #[test]
+    fn test_capture_output() {
+        for mut rox in sessions() {
+            let capture = Capture::new();
+            rox.set_output(capture.clone());
+            rox.eval_str(
+                "fun f() {} print 1 + 1; print 2.5; print nil; print 1 < 2; print f; print clock;",
+            )
+            .unwrap();
+
+            assert_eq!(
+                capture.contents(),
+                "2\n2.5\nnil\ntrue\n<fn f>\n<native fn>\n"
+            );
+        }
+    }
+
+    /// A sink that refuses every write, like stdout piped into a closed reader.
+    struct BrokenPipe;
+
+    impl Write for BrokenPipe {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_failed_print_is_a_runtime_error() {
+        for mut rox in sessions() {
+            rox.set_output(BrokenPipe);
+
+            let errors = rox.eval_str("var a = 1;\nprint a;").unwrap_err();
+            assert!(errors[0].is_runtime());
+            assert!(matches!(
+                &errors[0],
+                RoxError::RuntimeError(token, message, _)
+                    if token.lexeme == "print"
+                        && token.line == 2
+                        && message.starts_with("Could not write output: ")
+            ));
+        }
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        let mut rox = Rox::new();
+
+        let errors = rox.eval_str("print ;\nvar = 1;").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(!errors[0].is_runtime());
+
+        let errors = rox.eval_str("print -nil;").unwrap_err();
+        assert!(errors[0].is_runtime());
+
+        let errors = rox.eval_file("examples/does_not_exist.lox").unwrap_err();
+        assert!(matches!(errors[0], RoxError::IoError(_)));
+    }
+}
@@ -0,0 +1,249 @@
+//! Lists: the storage behind `Value::List`, indexing, and the natives that work on them.
+
+use crate::ast::Value;
+use crate::error::RoxError;
+use crate::function::RoxFunction;
+use crate::gc::Trace;
+use crate::native::{self, Arity};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A list's elements, shared by every value that refers to it.
+pub type List = Rc<RefCell<Vec<Value>>>;
+
+impl Trace for Vec<Value> {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        for value in self {
+            value.trace(visit);
+        }
+    }
+
+    fn clear(&mut self) {
+        Vec::clear(self);
+    }
+}
+
+/// Checks that `index` picks out one of `len` elements, returning it as a `usize`.
+pub fn index(index: &Value, len: usize) -> Result<usize, String> {
+    position(index, len).and_then(|i| {
+        if i < len {
+            Ok(i)
+        } else {
+            Err("List index out of range.".to_string())
+        }
+    })
+}
+
+/// Like `index`, but also accepts `len` itself: the position just past the end.
+fn position(index: &Value, len: usize) -> Result<usize, String> {
+    match index {
+        Value::Number(n) if n.fract() == 0.0 => {
+            if *n >= 0.0 && *n <= len as f64 {
+                Ok(*n as usize)
+            } else {
+                Err("List index out of range.".to_string())
+            }
+        }
+        _ => Err("List index must be an integer.".to_string()),
+    }
+}
+
+/// Reads `list[index]`.
+pub fn get(list: &List, i: &Value) -> Result<Value, String> {
+    let list = list.borrow();
+    Ok(list[index(i, list.len())?].clone())
+}
+
+/// Performs `list[index] = value`.
+pub fn set(list: &List, i: &Value, value: Value) -> Result<(), String> {
+    let mut list = list.borrow_mut();
+    let i = index(i, list.len())?;
+    list[i] = value;
+    Ok(())
+}
+
+pub fn natives() -> Vec<RoxFunction> {
+    vec![
+        RoxFunction::native("len", Arity::Fixed(1), len),
+        RoxFunction::native("push", Arity::Fixed(2), push),
+        RoxFunction::native("pop", Arity::Fixed(1), pop),
+        RoxFunction::native("insert", Arity::Fixed(3), insert),
+        RoxFunction::native("remove", Arity::Fixed(2), remove),
+        RoxFunction::native("slice", Arity::Fixed(3), slice),
+        RoxFunction::native("contains", Arity::Fixed(2), contains),
+        RoxFunction::native("reverse", Arity::Fixed(1), reverse),
+    ]
+}
+
+fn list(value: &Value) -> Result<List, RoxError> {
+    List::try_from(value.clone())
+}
+
+/// `len(x)`: the number of elements in a list, entries in a map, or characters in a string.
+fn len(arguments: &[Value]) -> Result<Value, RoxError> {
+    let len = match &arguments[0] {
+        Value::String_(s) => s.chars().count(),
+        Value::Map(map) => map.borrow().len(),
+        Value::List(list) => list.borrow().len(),
+        value => return Err(native::mismatch("a string, list or map", value)),
+    };
+    Ok(Value::Number(len as f64))
+}
+
+/// `push(list, x)`: appends `x`.
+fn push(arguments: &[Value]) -> Result<Value, RoxError> {
+    list(&arguments[0])?.borrow_mut().push(arguments[1].clone());
+    Ok(Value::Nil)
+}
+
+/// `pop(list)`: removes and returns the last element.
+fn pop(arguments: &[Value]) -> Result<Value, RoxError> {
+    list(&arguments[0])?
+        .borrow_mut()
+        .pop()
+        .ok_or_else(|| RoxError::NativeError("Can't pop from an empty list.".to_string()))
+}
+
+/// `insert(list, i, x)`: puts `x` at index `i`, moving the elements from there on up one.
+fn insert(arguments: &[Value]) -> Result<Value, RoxError> {
+    let list = list(&arguments[0])?;
+    let mut list = list.borrow_mut();
+    let i = position(&arguments[1], list.len()).map_err(RoxError::NativeError)?;
+    list.insert(i, arguments[2].clone());
+    Ok(Value::Nil)
+}
+
+/// `remove(list, i)`: removes and returns the element at index `i`.
+fn remove(arguments: &[Value]) -> Result<Value, RoxError> {
+    let list = list(&arguments[0])?;
+    let mut list = list.borrow_mut();
+    let i = index(&arguments[1], list.len()).map_err(RoxError::NativeError)?;
+    Ok(list.remove(i))
+}
+
+/// `slice(list, start, end)`: a new list of the elements from `start` up to, but not
+/// including, `end`.
+fn slice(arguments: &[Value]) -> Result<Value, RoxError> {
+    let list = list(&arguments[0])?;
+    let list = list.borrow();
+    let start = position(&arguments[1], list.len()).map_err(RoxError::NativeError)?;
+    let end = position(&arguments[2], list.len()).map_err(RoxError::NativeError)?;
+    if start > end {
+        return Err(RoxError::NativeError(
+            "Slice start must not be after its end.".to_string(),
+        ));
+    }
+    Ok(list[start..end].to_vec().into())
+}
+
+/// `contains(list, x)`: whether any element equals `x`.
+fn contains(arguments: &[Value]) -> Result<Value, RoxError> {
+    let list = list(&arguments[0])?;
+    let found = list
+        .borrow()
+        .iter()
+        .any(|value| value.equals(&arguments[1]));
+    Ok(Value::Bool(found))
+}
+
+/// `reverse(list)`: reverses the elements in place.
+fn reverse(arguments: &[Value]) -> Result<Value, RoxError> {
+    list(&arguments[0])?.borrow_mut().reverse();
+    Ok(Value::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(values: &[f64]) -> Value {
+        values
+            .iter()
+            .map(|&n| Value::Number(n))
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    fn error(result: Result<Value, RoxError>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_index() {
+        assert_eq!(index(&Value::Number(1.0), 2), Ok(1));
+        assert_eq!(
+            index(&Value::Number(2.0), 2),
+            Err("List index out of range.".to_string())
+        );
+        assert_eq!(
+            index(&Value::Number(-1.0), 2),
+            Err("List index out of range.".to_string())
+        );
+        assert_eq!(
+            index(&Value::Number(0.5), 2),
+            Err("List index must be an integer.".to_string())
+        );
+        assert_eq!(
+            index(&Value::Nil, 2),
+            Err("List index must be an integer.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_natives() {
+        let list = numbers(&[1.0, 2.0]);
+        let n = |n: f64| Value::Number(n);
+
+        push(&[list.clone(), n(3.0)]).unwrap();
+        insert(&[list.clone(), n(0.0), n(0.0)]).unwrap();
+        assert_eq!(list.to_string(), "[0, 1, 2, 3]");
+        assert_eq!(len(std::slice::from_ref(&list)).unwrap(), n(4.0));
+
+        assert_eq!(pop(std::slice::from_ref(&list)).unwrap(), n(3.0));
+        assert_eq!(remove(&[list.clone(), n(1.0)]).unwrap(), n(1.0));
+        reverse(std::slice::from_ref(&list)).unwrap();
+        assert_eq!(list.to_string(), "[2, 0]");
+
+        assert_eq!(
+            contains(&[list.clone(), n(2.0)]).unwrap(),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            contains(&[list.clone(), n(1.0)]).unwrap(),
+            Value::Bool(false)
+        );
+        assert_eq!(
+            slice(&[list.clone(), n(1.0), n(2.0)]).unwrap().to_string(),
+            "[0]"
+        );
+        assert_eq!(len(&[Value::from("héllo")]).unwrap(), n(5.0));
+    }
+
+    #[test]
+    fn test_native_errors() {
+        let empty = numbers(&[]);
+        let n = |n: f64| Value::Number(n);
+
+        assert_eq!(
+            error(pop(std::slice::from_ref(&empty))),
+            "Can't pop from an empty list."
+        );
+        assert_eq!(
+            error(remove(&[empty.clone(), n(0.0)])),
+            "List index out of range."
+        );
+        assert_eq!(
+            error(insert(&[empty.clone(), n(1.0), Value::Nil])),
+            "List index out of range."
+        );
+        assert_eq!(
+            error(slice(&[numbers(&[1.0, 2.0]), n(2.0), n(1.0)])),
+            "Slice start must not be after its end."
+        );
+        assert_eq!(error(push(&[n(1.0), n(2.0)])), "Expected a list but got 1.");
+        assert_eq!(
+            error(len(&[Value::Bool(true)])),
+            "Expected a string, list or map but got true."
+        );
+    }
+}
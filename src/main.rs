@@ -1,61 +1,229 @@
-pub mod ast;
-pub mod callable;
-pub mod environment;
-pub mod error;
-pub mod function;
-pub mod interpreter;
-pub mod parser;
-pub mod scanner;
-pub mod token;
-
-use clap::{arg, command};
-use interpreter::Interpreter;
-use parser::Parser;
-use scanner::Scanner;
-use std::fs::File;
-use std::io::prelude::*;
-
-fn run(contents: String) {
-    let mut scanner = Scanner::new(contents);
-    let tokens = scanner.scan_tokens();
-    let mut parser = Parser::new(tokens);
-    let statements = parser.parse();
-    let mut interpreter = Interpreter::new();
-    interpreter.interpret(&statements);
+mod repl;
+
+use clap::{arg, command, Command};
+use repl::Repl;
+use rox::compiler::Compiler;
+use rox::dump::{self, AstFormat};
+use rox::error::{ErrorHandler, RoxError};
+use rox::parser::Parser;
+use rox::resolver::Resolver;
+use rox::scanner::Scanner;
+use rox::{Backend, Rox};
+use std::fs;
+use std::process::ExitCode;
+
+// Exit statuses from sysexits.h.
+/// The script has a scan, parse or resolve error.
+const EX_DATAERR: u8 = 65;
+/// The script file couldn't be read.
+const EX_NOINPUT: u8 = 66;
+/// The script failed at runtime.
+const EX_SOFTWARE: u8 = 70;
+
+/// How `run` executes a script.
+#[derive(Clone, Copy, Debug, Default)]
+struct Options {
+    backend: Backend,
+    /// Collect garbage before every allocation.
+    gc_stress: bool,
+    /// Print the collector's statistics to stderr once the script finishes.
+    gc_stats: bool,
+}
+
+/// Runs a whole script, reporting any errors to stderr. On failure, returns the exit status
+/// the process should end with.
+fn run(contents: String, options: Options) -> Result<(), u8> {
+    let mut rox = Rox::with_backend(options.backend);
+    rox.heap().stress = options.gc_stress;
+    let result = rox.eval_str(&contents);
+    if options.gc_stats {
+        eprintln!("{}", rox.heap().stats());
+    }
 
-    println!("Tokens: {:?}", parser);
+    result.map_err(|errors| {
+        let handler = ErrorHandler::new(&contents);
+        for err in &errors {
+            handler.error(err);
+        }
+        if errors.iter().any(RoxError::is_runtime) {
+            EX_SOFTWARE
+        } else {
+            EX_DATAERR
+        }
+    })
 }
 
-fn run_file(file_path: &str) {
-    let mut f = File::open(file_path).expect("Path does not exist");
-    let mut contents = String::new();
+fn read_file(file_path: &str) -> Result<String, u8> {
+    fs::read_to_string(file_path).map_err(|err| {
+        eprintln!("Could not read '{}': {}", file_path, err);
+        EX_NOINPUT
+    })
+}
+
+fn run_file(file_path: &str, options: Options) -> Result<(), u8> {
+    run(read_file(file_path)?, options)
+}
+
+/// Prints the tokens, syntax tree and bytecode for a script, stopping at the first stage
+/// that reports errors. A script the compiler rejects, for going past one of a chunk's
+/// limits, still gets its tokens and syntax tree, with a note in place of the bytecode.
+fn dump(contents: String, format: AstFormat) -> Result<String, u8> {
+    let handler = ErrorHandler::new(&contents);
+    let report = |errors: &[RoxError]| {
+        for err in errors {
+            handler.error(err);
+        }
+        EX_DATAERR
+    };
+
+    let mut scanner = Scanner::new(contents.clone());
+    let tokens = scanner.scan_tokens();
+    if !scanner.errors.is_empty() {
+        return Err(report(&scanner.errors));
+    }
+    let mut out = format!("== tokens ==\n{}", dump::tokens(&tokens));
+
+    let (mut statements, errors) = Parser::new(tokens).parse();
+    if !errors.is_empty() {
+        return Err(report(&errors));
+    }
+    Resolver::new()
+        .resolve(&mut statements)
+        .map_err(|errors| report(&errors))?;
+    out.push_str(&format!("\n== ast ==\n{}", dump::ast(&statements, format)));
 
-    f.read_to_string(&mut contents)
-        .expect("Unable to read file");
-    run(contents);
+    match Compiler::new().compile(&statements) {
+        Ok(script) => out.push_str(&format!("\n{}", script.disassemble())),
+        Err(RoxError::CompileError(token, message)) => out.push_str(&format!(
+            "\nNo bytecode: {} [line {}]\n",
+            message, token.line
+        )),
+        Err(err) => return Err(report(&[err])),
+    }
+    Ok(out)
 }
 
 fn run_prompt() {
-    unimplemented!()
+    Repl::new().run();
 }
 
-fn main() {
-    let matches = command!().arg(arg!([script])).get_matches();
+fn main() -> ExitCode {
+    let matches = command!()
+        .arg(arg!([script]))
+        .subcommand(
+            Command::new("dump")
+                .about("Print the tokens, syntax tree and bytecode for a script")
+                .arg(arg!(<script>))
+                .arg(
+                    arg!(--ast <FORMAT> "How to lay out the syntax tree")
+                        .required(false)
+                        .possible_values(["tree", "sexpr"])
+                        .default_value("tree"),
+                ),
+        )
+        .arg(
+            arg!(--backend <BACKEND>)
+                .help("How scripts are executed")
+                .required(false)
+                .possible_values(["interpreter", "vm"])
+                .default_value("interpreter"),
+        )
+        .arg(arg!(--"gc-stress" "Collect garbage before every allocation"))
+        .arg(arg!(--"gc-stats" "Print garbage collector statistics when the script finishes"))
+        .get_matches();
+
+    let options = Options {
+        backend: match matches.value_of("backend") {
+            Some("vm") => Backend::Vm,
+            _ => Backend::Interpreter,
+        },
+        gc_stress: matches.is_present("gc-stress"),
+        gc_stats: matches.is_present("gc-stats"),
+    };
 
-    if let Some(script) = matches.value_of("script") {
-        println!("Value for script: {}", script);
-        run_file(script);
+    let result = if let Some(matches) = matches.subcommand_matches("dump") {
+        let format = match matches.value_of("ast") {
+            Some("sexpr") => AstFormat::SExpr,
+            _ => AstFormat::Tree,
+        };
+        let script = matches.value_of("script").expect("The script is required.");
+        read_file(script)
+            .and_then(|contents| dump(contents, format))
+            .map(|listing| print!("{}", listing))
+    } else if let Some(script) = matches.value_of("script") {
+        run_file(script, options)
     } else {
-        println!("Usage: rox [script]");
+        run_prompt();
+        Ok(())
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(status) => ExitCode::from(status),
     }
 }
 
 #[cfg(test)]
-
 mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_statuses() {
+        assert_eq!(run("var a = 1;".to_string(), Options::default()), Ok(()));
+        assert_eq!(
+            run("var a = @;".to_string(), Options::default()),
+            Err(EX_DATAERR)
+        );
+        assert_eq!(
+            run("print (1;".to_string(), Options::default()),
+            Err(EX_DATAERR)
+        );
+        assert_eq!(
+            run("return 1;".to_string(), Options::default()),
+            Err(EX_DATAERR)
+        );
+        assert_eq!(
+            run("print -\"a\";".to_string(), Options::default()),
+            Err(EX_SOFTWARE)
+        );
+        assert_eq!(
+            run_file("examples/does_not_exist.lox", Options::default()),
+            Err(EX_NOINPUT)
+        );
+    }
 
     #[test]
-    fn test_main() {
-        assert!(true);
+    fn test_dump() {
+        let listing = dump("print 1;".to_string(), AstFormat::SExpr).unwrap();
+
+        assert_eq!(
+            listing,
+            "== tokens ==
+1:1      Print         print
+1:7      Number        1
+1:8      Semicolon     ;
+1:9      Eof
+
+== ast ==
+(print 1)
+
+== <script> ==
+0000    1 Constant            0 1
+0001    | Print
+0002    | Nil
+0003    | Return
+"
+        );
+
+        let listing = dump(
+            "class A { m() { return 1; } }\nprint A().m();".to_string(),
+            AstFormat::SExpr,
+        )
+        .unwrap();
+
+        assert!(listing.starts_with("== tokens ==\n1:1      Class         class\n"));
+        assert!(listing.contains("\n== ast ==\n(class A (fun m() (return 1)))\n"));
+        assert!(listing.contains("\n0001    | Class               0 \"A\" 1\n"));
+        assert!(listing.ends_with("\n== <fn m> ==\n0000    1 Constant            0 1\n0001    | Return\n0002    | Nil\n0003    | Return\n"));
     }
 }
@@ -0,0 +1,263 @@
+//! Maps: the storage behind `Value::Map`, and the natives that work on them.
+
+use crate::ast::Value;
+use crate::error::RoxError;
+use crate::function::RoxFunction;
+use crate::gc::Trace;
+use crate::native::Arity;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A map's entries, shared by every value that refers to it.
+pub type MapRef = Rc<RefCell<Map>>;
+
+/// The values that can be map keys: the ones compared by what they contain rather than by
+/// identity.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Key {
+    Nil,
+    Bool(bool),
+    /// The bits of the number, with `-0` folded into `0` so the two find the same entry.
+    Number(u64),
+    String_(Rc<str>),
+}
+
+impl TryFrom<&Value> for Key {
+    type Error = String;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Nil => Ok(Key::Nil),
+            Value::Bool(b) => Ok(Key::Bool(*b)),
+            Value::Number(n) if n.is_nan() => Err("Map keys can't be nan.".to_string()),
+            Value::Number(n) => Ok(Key::Number((n + 0.0).to_bits())),
+            Value::String_(s) => Ok(Key::String_(Rc::clone(s))),
+            _ => Err("Only strings, numbers, booleans and nil can be map keys.".to_string()),
+        }
+    }
+}
+
+impl From<&Key> for Value {
+    fn from(key: &Key) -> Self {
+        match key {
+            Key::Nil => Value::Nil,
+            Key::Bool(b) => Value::Bool(*b),
+            Key::Number(bits) => Value::Number(f64::from_bits(*bits)),
+            Key::String_(s) => Value::String_(Rc::clone(s)),
+        }
+    }
+}
+
+/// Entries in the order they were first inserted.
+#[derive(Debug, Default)]
+pub struct Map {
+    entries: Vec<(Key, Value)>,
+    // Where each key's entry is in `entries`.
+    index: HashMap<Key, usize>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        self.index.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    /// Sets `key` to `value`. A key that is already present keeps its place in the order.
+    pub fn insert(&mut self, key: Key, value: Value) {
+        match self.index.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &Key) -> Option<Value> {
+        let i = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for (key, _) in &self.entries[i..] {
+            *self.index.get_mut(key).expect("Every entry is indexed.") -= 1;
+        }
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Value)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    /// Drops every entry, for the garbage collector.
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.index.clear();
+    }
+}
+
+impl Trace for Map {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        // Keys are never objects.
+        for (_, value) in &self.entries {
+            value.trace(visit);
+        }
+    }
+
+    fn clear(&mut self) {
+        Map::clear(self);
+    }
+}
+
+/// Reads `map[key]`.
+pub fn get(map: &MapRef, key: &Value) -> Result<Value, String> {
+    map.borrow()
+        .get(&Key::try_from(key)?)
+        .cloned()
+        .ok_or_else(|| format!("Undefined key {:?}.", key))
+}
+
+/// Performs `map[key] = value`.
+pub fn set(map: &MapRef, key: &Value, value: Value) -> Result<(), String> {
+    map.borrow_mut().insert(Key::try_from(key)?, value);
+    Ok(())
+}
+
+pub fn natives() -> Vec<RoxFunction> {
+    vec![
+        RoxFunction::native("keys", Arity::Fixed(1), keys),
+        RoxFunction::native("values", Arity::Fixed(1), values),
+        RoxFunction::native("has", Arity::Fixed(2), has),
+        RoxFunction::native("delete", Arity::Fixed(2), delete),
+    ]
+}
+
+fn map(value: &Value) -> Result<MapRef, RoxError> {
+    MapRef::try_from(value.clone())
+}
+
+fn key(value: &Value) -> Result<Key, RoxError> {
+    Key::try_from(value).map_err(RoxError::NativeError)
+}
+
+/// `keys(map)`: a list of the keys, in insertion order.
+fn keys(arguments: &[Value]) -> Result<Value, RoxError> {
+    let map = map(&arguments[0])?;
+    let keys: Vec<Value> = map.borrow().iter().map(|(key, _)| key.into()).collect();
+    Ok(keys.into())
+}
+
+/// `values(map)`: a list of the values, in the same order as `keys`.
+fn values(arguments: &[Value]) -> Result<Value, RoxError> {
+    let map = map(&arguments[0])?;
+    let values: Vec<Value> = map
+        .borrow()
+        .iter()
+        .map(|(_, value)| value.clone())
+        .collect();
+    Ok(values.into())
+}
+
+/// `has(map, key)`: whether `key` has an entry.
+fn has(arguments: &[Value]) -> Result<Value, RoxError> {
+    let found = map(&arguments[0])?
+        .borrow()
+        .get(&key(&arguments[1])?)
+        .is_some();
+    Ok(Value::Bool(found))
+}
+
+/// `delete(map, key)`: removes the entry for `key`, returning its value, or `nil` if there
+/// was none.
+fn delete(arguments: &[Value]) -> Result<Value, RoxError> {
+    let removed = map(&arguments[0])?
+        .borrow_mut()
+        .remove(&key(&arguments[1])?);
+    Ok(removed.unwrap_or(Value::Nil))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(value: impl Into<Value>) -> Key {
+        Key::try_from(&value.into()).unwrap()
+    }
+
+    #[test]
+    fn test_map_keeps_insertion_order() {
+        let mut map = Map::new();
+        map.insert(key("b"), Value::Number(1.0));
+        map.insert(key(2.0), Value::Number(2.0));
+        map.insert(key(true), Value::Number(3.0));
+        map.insert(key("b"), Value::Number(4.0));
+
+        assert_eq!(map.remove(&key(2.0)), Some(Value::Number(2.0)));
+        assert_eq!(map.remove(&key(2.0)), None);
+        map.insert(key(()), Value::Number(5.0));
+
+        let entries: Vec<(Value, Value)> = map
+            .iter()
+            .map(|(key, value)| (key.into(), value.clone()))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (Value::from("b"), Value::Number(4.0)),
+                (Value::Bool(true), Value::Number(3.0)),
+                (Value::Nil, Value::Number(5.0)),
+            ]
+        );
+        assert_eq!(map.get(&key(true)), Some(&Value::Number(3.0)));
+    }
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(key(-0.0), key(0.0));
+        assert_ne!(key("1"), key(1.0));
+        assert_eq!(
+            Key::try_from(&Value::Number(f64::NAN)),
+            Err("Map keys can't be nan.".to_string())
+        );
+        assert_eq!(
+            Key::try_from(&Value::from(Vec::new())),
+            Err("Only strings, numbers, booleans and nil can be map keys.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_natives() {
+        let map = Value::Map(Rc::new(RefCell::new(Map::new())));
+        set(
+            &MapRef::try_from(map.clone()).unwrap(),
+            &"a".into(),
+            1.0.into(),
+        )
+        .unwrap();
+        let call = |native: fn(&[Value]) -> Result<Value, RoxError>, key: &str| {
+            native(&[map.clone(), key.into()]).unwrap()
+        };
+
+        assert_eq!(call(has, "a"), Value::Bool(true));
+        assert_eq!(
+            keys(std::slice::from_ref(&map)).unwrap().to_string(),
+            "[\"a\"]"
+        );
+        assert_eq!(
+            values(std::slice::from_ref(&map)).unwrap().to_string(),
+            "[1]"
+        );
+        assert_eq!(call(delete, "a"), Value::Number(1.0));
+        assert_eq!(call(delete, "a"), Value::Nil);
+        assert_eq!(call(has, "a"), Value::Bool(false));
+    }
+}
@@ -0,0 +1,245 @@
+//! Functions written in Rust that scripts can call, and the conversions they use to move
+//! values across.
+//!
+//! Both backends start out with the `standard_library` defined as globals. Embedders add their
+//! own with `Interpreter::define_native` or `Vm::define_native`.
+
+use crate::ast::Value;
+use crate::error::RoxError;
+use crate::function::RoxFunction;
+use crate::list::{self, List};
+use crate::map::{self, MapRef};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The body of a native function. Errors are raised as `RoxError::NativeError` and located
+/// at the call by the backend.
+pub type NativeFn = dyn Fn(&[Value]) -> Result<Value, RoxError>;
+
+/// How many arguments a function accepts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arity {
+    Fixed(usize),
+    /// At least this many.
+    Variadic(usize),
+}
+
+impl Arity {
+    /// Checks an argument count, describing the mismatch if there is one.
+    pub fn check(self, count: usize) -> Result<(), String> {
+        match self {
+            Arity::Fixed(arity) if count != arity => {
+                Err(format!("Expected {} arguments but got {}.", arity, count))
+            }
+            Arity::Variadic(minimum) if count < minimum => Err(format!(
+                "Expected at least {} arguments but got {}.",
+                minimum, count
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arity::Fixed(arity) => write!(f, "{}", arity),
+            Arity::Variadic(minimum) => write!(f, "{}+", minimum),
+        }
+    }
+}
+
+/// The natives every script can call.
+pub fn standard_library() -> Vec<RoxFunction> {
+    let mut natives = vec![
+        RoxFunction::native("clock", Arity::Fixed(0), clock),
+        RoxFunction::native("str", Arity::Fixed(1), str),
+        RoxFunction::native("repr", Arity::Fixed(1), repr),
+    ];
+    natives.extend(list::natives());
+    natives.extend(map::natives());
+    natives
+}
+
+/// `clock()`: milliseconds since the Unix epoch.
+fn clock(_arguments: &[Value]) -> Result<Value, RoxError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|err| RoxError::NativeError(format!("Could not retrieve time: {}.", err)))?;
+    Ok(Value::Number(now.as_millis() as f64))
+}
+
+/// `str(x)`: `x` as `print` would show it.
+fn str(arguments: &[Value]) -> Result<Value, RoxError> {
+    Ok(match &arguments[0] {
+        Value::String_(s) => Value::String_(Rc::clone(s)),
+        value => value.to_string().into(),
+    })
+}
+
+/// `repr(x)`: `x` with strings quoted and escaped.
+fn repr(arguments: &[Value]) -> Result<Value, RoxError> {
+    Ok(arguments[0].repr().into())
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String_(s.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String_(s.into())
+    }
+}
+
+impl From<Rc<str>> for Value {
+    fn from(s: Rc<str>) -> Self {
+        Value::String_(s)
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Value::Nil
+    }
+}
+
+/// Makes a new list. The backends hand it to their heap when it reaches them.
+impl From<Vec<Value>> for Value {
+    fn from(elements: Vec<Value>) -> Self {
+        Value::List(Rc::new(RefCell::new(elements)))
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(option: Option<T>) -> Self {
+        option.map_or(Value::Nil, Into::into)
+    }
+}
+
+/// The error for a native given `value` where it needs something else.
+pub(crate) fn mismatch(expected: &str, value: &Value) -> RoxError {
+    RoxError::NativeError(format!("Expected {} but got {:?}.", expected, value))
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = RoxError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(n) => Ok(n),
+            value => Err(mismatch("a number", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = RoxError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            value => Err(mismatch("a boolean", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for Rc<str> {
+    type Error = RoxError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String_(s) => Ok(s),
+            value => Err(mismatch("a string", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for List {
+    type Error = RoxError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::List(list) => Ok(list),
+            value => Err(mismatch("a list", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for MapRef {
+    type Error = RoxError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Map(map) => Ok(map),
+            value => Err(mismatch("a map", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = RoxError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        Rc::<str>::try_from(value).map(|s| s.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arity_check() {
+        assert_eq!(Arity::Fixed(2).check(2), Ok(()));
+        assert_eq!(
+            Arity::Fixed(2).check(1),
+            Err("Expected 2 arguments but got 1.".to_string())
+        );
+        assert_eq!(Arity::Variadic(1).check(3), Ok(()));
+        assert_eq!(
+            Arity::Variadic(1).check(0),
+            Err("Expected at least 1 arguments but got 0.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_str_and_repr() {
+        let text = Value::from("say \"hi\"\n");
+
+        assert_eq!(str(std::slice::from_ref(&text)).unwrap(), text);
+        assert_eq!(repr(&[text]).unwrap(), Value::from("\"say \\\"hi\\\"\\n\""));
+        assert_eq!(str(&[Value::Number(2.0)]).unwrap(), Value::from("2"));
+        assert_eq!(repr(&[Value::Nil]).unwrap(), Value::from("nil"));
+        assert_eq!(str(&[Value::Number(f64::NAN)]).unwrap(), Value::from("nan"));
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(Value::from(1.5), Value::Number(1.5));
+        assert_eq!(Value::from("a"), Value::String_("a".into()));
+        assert_eq!(Value::from(None::<bool>), Value::Nil);
+        assert_eq!(f64::try_from(Value::Number(2.0)).unwrap(), 2.0);
+        assert_eq!(String::try_from(Value::from("b")).unwrap(), "b");
+        assert_eq!(
+            bool::try_from(Value::from("b")).unwrap_err().to_string(),
+            "Expected a boolean but got \"b\"."
+        );
+    }
+}
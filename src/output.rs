@@ -0,0 +1,79 @@
+//! Where `print` statements write to.
+
+use crate::ast::Value;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// The destination for a backend's `print` output. Defaults to stdout.
+pub struct Output {
+    writer: Box<dyn Write>,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self::new(Box::new(io::stdout()))
+    }
+}
+
+impl Output {
+    pub fn new(writer: Box<dyn Write>) -> Self {
+        Self { writer }
+    }
+
+    /// Writes `value` on a line of its own, formatted as Lox displays it.
+    pub fn print(&mut self, value: &Value) -> io::Result<()> {
+        writeln!(self.writer, "{}", value)
+    }
+}
+
+/// A writer that keeps everything written to it, for reading back what a script printed.
+/// Clones share the same buffer, so one can be handed to a backend and another kept.
+#[derive(Clone, Debug, Default)]
+pub struct Capture {
+    buffer: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.buffer.borrow()).into_owned()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_print_formats_values() {
+        let capture = Capture::new();
+        let mut output = Output::new(Box::new(capture.clone()));
+
+        for value in [
+            Value::Number(3.0),
+            Value::Number(-0.5),
+            Value::Nil,
+            Value::Bool(false),
+            Value::from("text"),
+        ] {
+            output.print(&value).unwrap();
+        }
+
+        assert_eq!(capture.contents(), "3\n-0.5\nnil\nfalse\ntext\n");
+    }
+}
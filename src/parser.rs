@@ -3,9 +3,11 @@ use crate::error::RoxError;
 use crate::token::Literal;
 use crate::token::Token;
 use crate::token::TokenType::{
-    self, And, Bang, BangEqual, Comma, Else, Eof, Equal, EqualEqual, False, For, Fun, Greater,
-    GreaterEqual, Identifier, If, LeftBrace, LeftParen, Less, LessEqual, Minus, Nil, Number, Or,
-    Plus, Print, RightBrace, RightParen, Semicolon, Slash, Star, String_, True, Var, While,
+    self, And, Bang, BangEqual, Break, Catch, Class, Colon, Comma, Continue, Dot, Else, Eof, Equal,
+    EqualEqual, False, Finally, For, Fun, Greater, GreaterEqual, Identifier, If, LeftBrace,
+    LeftBracket, LeftParen, Less, LessEqual, Minus, Nil, Number, Or, Plus, Print, Return,
+    RightBrace, RightBracket, RightParen, Semicolon, Slash, Star, String_, Super, This, Throw,
+    True, Try, Var, While,
 };
 use std::result::Result;
 
@@ -13,23 +15,44 @@ use std::result::Result;
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    errors: Vec<RoxError>,
+    // How many loops enclose the current statement within its function, for rejecting a
+    // `break` or `continue` with nothing to leave.
+    loop_depth: usize,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens,
+            current: 0,
+            errors: Vec::new(),
+            loop_depth: 0,
+        }
     }
 
-    pub fn parse(&mut self) -> Vec<Stmt> {
+    /// Parses every declaration it can. A syntax error skips ahead to the next statement
+    /// boundary, so the returned errors cover the whole script, not just its first mistake.
+    pub fn parse(&mut self) -> (Vec<Stmt>, Vec<RoxError>) {
         let mut statements: Vec<Stmt> = Vec::new();
         while !self.is_at_end() {
-            statements.push(self.declaration().unwrap());
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
         }
 
-        statements
+        (statements, std::mem::take(&mut self.errors))
     }
 
     fn statement(&mut self) -> Result<Stmt, RoxError> {
+        if self.match_types([Break].to_vec()) {
+            return self.loop_control_statement(Stmt::Break);
+        }
+
+        if self.match_types([Continue].to_vec()) {
+            return self.loop_control_statement(Stmt::Continue);
+        }
+
         if self.match_types([For].to_vec()) {
             return self.for_statement();
         }
@@ -42,6 +65,18 @@ impl Parser {
             return self.print_statement();
         }
 
+        if self.match_types([Return].to_vec()) {
+            return self.return_statement();
+        }
+
+        if self.match_types([Throw].to_vec()) {
+            return self.throw_statement();
+        }
+
+        if self.match_types([Try].to_vec()) {
+            return self.try_statement();
+        }
+
         if self.match_types([While].to_vec()) {
             return self.while_statement();
         }
@@ -50,48 +85,64 @@ impl Parser {
             return Ok(Stmt::Block(self.block()?));
         }
 
-        return self.expression_statement();
+        self.expression_statement()
     }
 
     fn function(&mut self, kind: String) -> Result<Stmt, RoxError> {
-        let name = self.consume(
-            Identifier,
-            "Expect".to_owned() + &kind + &"name.".to_string(),
-        )?;
-
-        self.consume(
-            LeftParen,
-            "Expect ( after".to_owned() + &kind + &"name.".to_string(),
-        );
+        let name = self.consume(Identifier, format!("Expect {} name.", kind))?;
+        self.consume(LeftParen, format!("Expect '(' after {} name.", kind))?;
+        let (parameters, body) = self.function_body(&kind)?;
+        Ok(Stmt::Function(name, parameters, body))
+    }
 
+    /// Parses the parameters and body of a function, from just after the opening `(`.
+    fn function_body(&mut self, kind: &str) -> Result<(Vec<Token>, Vec<Stmt>), RoxError> {
         let mut parameters = Vec::new();
 
         if !self.check(RightParen) {
-            parameters.push(self.consume(Identifier, "Expect parameter name.".to_string())?);
             loop {
                 if parameters.len() >= 255 {
-                    return Err(RoxError::MaxParameterLimitError);
-                } else if !self.match_types([Comma].to_vec()) {
+                    self.error(self.peek(), "Can't have more than 255 parameters.");
+                }
+                parameters.push(self.consume(Identifier, "Expect parameter name.".to_string())?);
+
+                if !self.match_types([Comma].to_vec()) {
                     break;
-                } else {
-                    parameters
-                        .push(self.consume(Identifier, "Expect parameter name.".to_string())?);
                 }
             }
         }
 
-        self.consume(RightParen, "Expect ')' after parameters".to_string());
-        self.consume(
-            LeftBrace,
-            "Expect '{' before".to_owned() + &kind + &"name.".to_string(),
-        );
+        self.consume(RightParen, "Expect ')' after parameters.".to_string())?;
+        self.consume(LeftBrace, format!("Expect '{{' before {} body.", kind))?;
 
-        let body = self.block()?;
-        Ok(Stmt::Function(name, parameters, body))
+        // Loops around the function don't carry into its body.
+        let enclosing_loops = std::mem::take(&mut self.loop_depth);
+        let body = self.block();
+        self.loop_depth = enclosing_loops;
+        Ok((parameters, body?))
     }
 
-    fn declaration(&mut self) -> Result<Stmt, RoxError> {
-        if self.match_types([Fun].to_vec()) {
+    /// Parses one declaration, recording any syntax error and resynchronizing instead of
+    /// giving up on the rest of the input.
+    fn declaration(&mut self) -> Option<Stmt> {
+        match self.try_declaration() {
+            Ok(statement) => Some(statement),
+            Err(err) => {
+                self.errors.push(err);
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    fn try_declaration(&mut self) -> Result<Stmt, RoxError> {
+        if self.match_types([Class].to_vec()) {
+            return self.class_declaration();
+        }
+
+        // `fun (` starts an expression statement with a lambda in it instead.
+        if self.check(Fun) && !self.check_next(LeftParen) {
+            self.advance();
             return self.function("function".to_string());
         }
 
@@ -102,11 +153,32 @@ impl Parser {
         self.statement()
     }
 
+    fn class_declaration(&mut self) -> Result<Stmt, RoxError> {
+        let name = self.consume(Identifier, "Expect class name.".to_string())?;
+
+        let superclass = if self.match_types([Less].to_vec()) {
+            self.consume(Identifier, "Expect superclass name.".to_string())?;
+            Some(Expr::Var(self.previous(), None))
+        } else {
+            None
+        };
+
+        self.consume(LeftBrace, "Expect '{' before class body.".to_string())?;
+
+        let mut methods = Vec::new();
+        while !self.check(RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method".to_string())?);
+        }
+
+        self.consume(RightBrace, "Expect '}' after class body.".to_string())?;
+        Ok(Stmt::Class(name, superclass, methods))
+    }
+
     fn var_declaration(&mut self) -> Result<Stmt, RoxError> {
         let token_name = self.consume(Identifier, "Expect variable name.".to_string())?;
 
         let initializer = if self.match_types([Equal].to_vec()) {
-            self.expression().ok()
+            Some(self.expression()?)
         } else {
             None
         };
@@ -119,7 +191,9 @@ impl Parser {
         let mut statements = Vec::new();
 
         while !self.check(RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration()?);
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
         }
 
         self.consume(RightBrace, "Expect '}' after block.".to_string())?;
@@ -132,13 +206,13 @@ impl Parser {
         let initializer = if self.match_types([Semicolon].to_vec()) {
             None
         } else if self.match_types([Var].to_vec()) {
-            self.var_declaration().ok()
+            Some(self.var_declaration()?)
         } else {
-            self.expression_statement().ok()
+            Some(self.expression_statement()?)
         };
 
         let condition = if !self.check(Semicolon) {
-            self.expression().ok()
+            Some(self.expression()?)
         } else {
             None
         };
@@ -146,27 +220,19 @@ impl Parser {
         self.consume(Semicolon, "Expect ';' after loop condition.".to_string())?;
 
         let increment = if !self.check(RightParen) {
-            self.expression().ok()
+            Some(self.expression()?)
         } else {
             None
         };
 
         self.consume(RightParen, "Expect ')' after for clauses.".to_string())?;
-        let mut body = self.statement()?;
-        if increment != None {
-            body = Stmt::Block(vec![body, Stmt::Expression(increment.unwrap())]);
-        }
+        let body = self.loop_body()?;
 
-        let condition = if condition == None {
-            Some(Expr::Literal(Literal::Bool(true)))
-        } else {
-            condition
-        };
-
-        body = Stmt::While(condition.unwrap(), Box::new(body));
+        let condition = condition.unwrap_or(Expr::Literal(Literal::Bool(true)));
+        let mut body = Stmt::While(condition, Box::new(body), increment);
 
-        if Some(initializer.as_ref().unwrap()) != None {
-            body = Stmt::Block(vec![initializer.unwrap(), body]);
+        if let Some(initializer) = initializer {
+            body = Stmt::Block(vec![initializer, body]);
         }
 
         Ok(body)
@@ -193,15 +259,89 @@ impl Parser {
         let condition = self.expression()?;
         self.consume(RightParen, "Expect ')' after condition.".to_string())?;
 
-        let body = self.statement()?;
+        let body = self.loop_body()?;
+
+        Ok(Stmt::While(condition, Box::new(body), None))
+    }
+
+    fn loop_body(&mut self) -> Result<Stmt, RoxError> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
+    }
 
-        Ok(Stmt::While(condition, Box::new(body)))
+    /// Parses the rest of a `break` or `continue`, which `make` turns into its statement.
+    fn loop_control_statement(&mut self, make: fn(Token) -> Stmt) -> Result<Stmt, RoxError> {
+        let keyword = self.previous();
+        if self.loop_depth == 0 {
+            self.error(
+                keyword.clone(),
+                &format!("Can't use '{}' outside of a loop.", keyword.lexeme),
+            );
+        }
+        self.consume(Semicolon, format!("Expect ';' after '{}'.", keyword.lexeme))?;
+        Ok(make(keyword))
     }
 
     fn print_statement(&mut self) -> Result<Stmt, RoxError> {
+        let keyword = self.previous();
         let value: Expr = self.expression()?;
         self.consume(Semicolon, "Expect ';' after value.".to_string())?;
-        Ok(Stmt::Print(value))
+        Ok(Stmt::Print(keyword, value))
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, RoxError> {
+        let keyword = self.previous();
+        let value = if !self.check(Semicolon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(Semicolon, "Expect ';' after return value.".to_string())?;
+        Ok(Stmt::Return(keyword, value))
+    }
+
+    fn throw_statement(&mut self) -> Result<Stmt, RoxError> {
+        let keyword = self.previous();
+        let value = self.expression()?;
+        self.consume(Semicolon, "Expect ';' after thrown value.".to_string())?;
+        Ok(Stmt::Throw(keyword, value))
+    }
+
+    fn try_statement(&mut self) -> Result<Stmt, RoxError> {
+        let keyword = self.previous();
+        self.consume(LeftBrace, "Expect '{' after 'try'.".to_string())?;
+        let body = self.block()?;
+
+        let catch = if self.match_types([Catch].to_vec()) {
+            self.consume(LeftParen, "Expect '(' after 'catch'.".to_string())?;
+            let name = self.consume(Identifier, "Expect exception variable name.".to_string())?;
+            self.consume(
+                RightParen,
+                "Expect ')' after exception variable.".to_string(),
+            )?;
+            self.consume(LeftBrace, "Expect '{' before catch body.".to_string())?;
+            Some((name, self.block()?))
+        } else {
+            None
+        };
+
+        let finally = if self.match_types([Finally].to_vec()) {
+            self.consume(LeftBrace, "Expect '{' after 'finally'.".to_string())?;
+            Some(self.block()?)
+        } else {
+            None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            return Err(RoxError::ParseError(
+                self.peek(),
+                "Expect 'catch' or 'finally' after try block.".to_string(),
+            ));
+        }
+        Ok(Stmt::Try(keyword, body, catch, finally))
     }
 
     fn expression_statement(&mut self) -> Result<Stmt, RoxError> {
@@ -221,10 +361,18 @@ impl Parser {
             let equals = self.previous();
             let value = self.assignment()?;
 
-            if let Expr::Var(name) = expr {
-                Ok(Expr::Assign(name, Box::new(value)))
-            } else {
-                Err(RoxError::InvalidAssignmentError(equals))
+            match expr {
+                Expr::Var(name, _) => Ok(Expr::Assign(name, Box::new(value), None)),
+                Expr::Get(object, name) => Ok(Expr::Set(object, name, Box::new(value))),
+                Expr::Index(object, bracket, index) => {
+                    Ok(Expr::SetIndex(object, bracket, index, Box::new(value)))
+                }
+                _ => {
+                    // The parser isn't confused about where it is, so there is no need to
+                    // synchronize; just record the error and carry on.
+                    self.errors.push(RoxError::InvalidAssignmentError(equals));
+                    Ok(expr)
+                }
             }
         } else {
             Ok(expr)
@@ -304,32 +452,46 @@ impl Parser {
         if self.match_types([Bang, Minus].to_vec()) {
             let operator: Token = self.previous();
             let right = self.unary()?;
-            return Ok(Expr::Unary(operator, Box::new(right)));
+            Ok(Expr::Unary(operator, Box::new(right)))
         } else {
             self.call()
         }
     }
 
     fn call(&mut self) -> Result<Expr, RoxError> {
-        let mut expr = self.primary();
+        let mut expr = self.primary()?;
 
         loop {
             if self.match_types([LeftParen].to_vec()) {
-                expr = self.finish_call(expr.unwrap());
+                expr = self.finish_call(expr)?;
+            } else if self.match_types([Dot].to_vec()) {
+                let name =
+                    self.consume(Identifier, "Expect property name after '.'.".to_string())?;
+                expr = Expr::Get(Box::new(expr), name);
+            } else if self.match_types([LeftBracket].to_vec()) {
+                let index = self.expression()?;
+                let bracket = self.consume(RightBracket, "Expect ']' after index.".to_string())?;
+                expr = Expr::Index(Box::new(expr), bracket, Box::new(index));
             } else {
                 break;
             }
         }
 
-        expr
+        Ok(expr)
     }
 
     fn finish_call(&mut self, callee: Expr) -> Result<Expr, RoxError> {
         let mut args = Vec::new();
         if !self.check(RightParen) {
-            args.push(self.expression()?);
-            while self.match_types([Comma].to_vec()) {
+            loop {
+                if args.len() >= 255 {
+                    self.error(self.peek(), "Can't have more than 255 arguments.");
+                }
                 args.push(self.expression()?);
+
+                if !self.match_types([Comma].to_vec()) {
+                    break;
+                }
             }
         }
         let paren = self.consume(RightParen, "Expect ')' after arguments.".to_string())?;
@@ -356,8 +518,19 @@ impl Parser {
             }));
         }
 
+        if self.match_types([Super].to_vec()) {
+            let keyword = self.previous();
+            self.consume(Dot, "Expect '.' after 'super'.".to_string())?;
+            let method = self.consume(Identifier, "Expect superclass method name.".to_string())?;
+            return Ok(Expr::Super(keyword, method, None));
+        }
+
+        if self.match_types([This].to_vec()) {
+            return Ok(Expr::This(self.previous(), None));
+        }
+
         if self.match_types([Identifier].to_vec()) {
-            return Ok(Expr::Var(self.previous()));
+            return Ok(Expr::Var(self.previous(), None));
         }
 
         if self.match_types([LeftParen].to_vec()) {
@@ -366,7 +539,50 @@ impl Parser {
             return Ok(Expr::Grouping(Box::new(expr)));
         }
 
-        Err(RoxError::UnexpectedError)
+        if self.match_types([Fun].to_vec()) {
+            let keyword = self.previous();
+            self.consume(LeftParen, "Expect '(' after 'fun'.".to_string())?;
+            let (parameters, body) = self.function_body("function")?;
+            return Ok(Expr::Lambda(keyword, parameters, body));
+        }
+
+        if self.match_types([LeftBracket].to_vec()) {
+            let mut elements = Vec::new();
+            if !self.check(RightBracket) {
+                loop {
+                    elements.push(self.expression()?);
+                    if !self.match_types([Comma].to_vec()) {
+                        break;
+                    }
+                }
+            }
+            let bracket =
+                self.consume(RightBracket, "Expect ']' after list elements.".to_string())?;
+            return Ok(Expr::List(bracket, elements));
+        }
+
+        // Statements starting with `{` are blocks, so this is only reached in expression
+        // position.
+        if self.match_types([LeftBrace].to_vec()) {
+            let mut entries = Vec::new();
+            if !self.check(RightBrace) {
+                loop {
+                    let key = self.expression()?;
+                    self.consume(Colon, "Expect ':' after map key.".to_string())?;
+                    entries.push((key, self.expression()?));
+                    if !self.match_types([Comma].to_vec()) {
+                        break;
+                    }
+                }
+            }
+            let brace = self.consume(RightBrace, "Expect '}' after map entries.".to_string())?;
+            return Ok(Expr::Map(brace, entries));
+        }
+
+        Err(RoxError::ParseError(
+            self.peek(),
+            "Expect expression.".to_string(),
+        ))
     }
 
     fn consume(&mut self, token_type: TokenType, message: String) -> Result<Token, RoxError> {
@@ -377,12 +593,36 @@ impl Parser {
         }
     }
 
+    fn error(&mut self, token: Token, message: &str) {
+        self.errors
+            .push(RoxError::ParseError(token, message.to_string()));
+    }
+
+    /// Discards tokens until the start of what looks like the next statement.
+    fn synchronize(&mut self) {
+        self.advance();
+
+        while !self.is_at_end() {
+            if self.previous().token_type == Semicolon {
+                return;
+            }
+
+            match self.peek().token_type {
+                Class | Fun | Var | For | If | While | Print | Return | Break | Continue
+                | Throw | Try => return,
+                _ => {
+                    self.advance();
+                }
+            }
+        }
+    }
+
     fn peek(&self) -> Token {
-        return self.tokens[self.current].clone();
+        self.tokens[self.current].clone()
     }
 
     fn is_at_end(&self) -> bool {
-        return self.peek().token_type == Eof;
+        self.peek().token_type == Eof
     }
 
     fn advance(&mut self) -> Token {
@@ -411,7 +651,15 @@ impl Parser {
             return false;
         }
 
-        return self.peek().token_type == token_type;
+        self.peek().token_type == token_type
+    }
+
+    /// Like `check`, but looks at the token after the next one.
+    fn check_next(&self, token_type: TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.token_type == token_type,
+            None => false,
+        }
     }
 }
 
@@ -419,6 +667,21 @@ impl Parser {
 mod tests {
     use super::*;
     use crate::ast;
+    use crate::scanner::Scanner;
+    use crate::token::Span;
+
+    /// Parses `tokens`, failing with the first syntax error if there were any.
+    fn parse_tokens(tokens: Vec<Token>) -> Result<Vec<Stmt>, RoxError> {
+        let (statements, errors) = Parser::new(tokens).parse();
+        match errors.into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(statements),
+        }
+    }
+
+    fn parse_source(source: &str) -> Result<Vec<Stmt>, RoxError> {
+        parse_tokens(Scanner::new(source.to_string()).scan_tokens())
+    }
 
     #[test]
     fn test_consume() {
@@ -438,32 +701,39 @@ mod tests {
                 lexeme: "print".to_string(),
                 literal: None,
                 line: 1,
+                span: Span::default(),
             },
             Token {
                 token_type: String_,
                 lexeme: "one".to_string(),
-                literal: Some(Literal::String_("one".to_string())),
+                literal: Some(Literal::String_("one".into())),
                 line: 1,
+                span: Span::default(),
             },
             Token {
                 token_type: Semicolon,
                 lexeme: ";".to_string(),
                 literal: None,
                 line: 1,
+                span: Span::default(),
             },
             Token {
                 token_type: Eof,
                 lexeme: "".to_string(),
                 literal: None,
                 line: 2,
+                span: Span::default(),
             },
         ];
 
         let mut parser = Parser::new(tokens.clone());
-        let statements = parser.parse();
+        let (statements, errors) = parser.parse();
+        assert!(errors.is_empty());
 
-        let expected_statement =
-            Stmt::Print(ast::Expr::Literal(Literal::String_("one".to_string())));
+        let expected_statement = Stmt::Print(
+            tokens[0].clone(),
+            ast::Expr::Literal(Literal::String_("one".into())),
+        );
 
         assert!(statements[0] == expected_statement);
     }
@@ -477,89 +747,356 @@ mod tests {
                 lexeme: "if".to_string(),
                 literal: None,
                 line: 1,
+                span: Span::default(),
             },
             Token {
                 token_type: LeftParen,
                 lexeme: "(".to_string(),
                 literal: None,
                 line: 1,
+                span: Span::default(),
             },
             Token {
                 token_type: True,
                 lexeme: "true".to_string(),
                 literal: None,
                 line: 1,
+                span: Span::default(),
             },
             Token {
                 token_type: RightParen,
                 lexeme: ")".to_string(),
                 literal: None,
                 line: 1,
+                span: Span::default(),
             },
             Token {
                 token_type: Identifier,
                 lexeme: "a".to_string(),
                 literal: None,
                 line: 1,
+                span: Span::default(),
             },
             Token {
                 token_type: Equal,
                 lexeme: "=".to_string(),
                 literal: None,
                 line: 1,
+                span: Span::default(),
             },
             Token {
                 token_type: Number,
                 lexeme: "1".to_string(),
                 literal: Some(Literal::Number(1.0)),
                 line: 1,
+                span: Span::default(),
             },
             Token {
                 token_type: Semicolon,
                 lexeme: ";".to_string(),
                 literal: None,
                 line: 1,
+                span: Span::default(),
             },
             Token {
                 token_type: Else,
                 lexeme: "else".to_string(),
                 literal: None,
                 line: 1,
+                span: Span::default(),
             },
             Token {
                 token_type: Identifier,
                 lexeme: "a".to_string(),
                 literal: None,
                 line: 1,
+                span: Span::default(),
             },
             Token {
                 token_type: Equal,
                 lexeme: "=".to_string(),
                 literal: None,
                 line: 1,
+                span: Span::default(),
             },
             Token {
                 token_type: Number,
                 lexeme: "2".to_string(),
                 literal: Some(Literal::Number(2.0)),
                 line: 1,
+                span: Span::default(),
             },
             Token {
                 token_type: Semicolon,
                 lexeme: ";".to_string(),
                 literal: None,
                 line: 1,
+                span: Span::default(),
             },
             Token {
                 token_type: Eof,
                 lexeme: "".to_string(),
                 literal: None,
                 line: 3,
+                span: Span::default(),
             },
         ];
 
-        let mut parser = Parser::new(tokens.clone());
-        parser.parse();
+        parse_tokens(tokens)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_class_declaration() -> Result<(), RoxError> {
+        let statements = parse_source("class A { m() { this.x = 1; } }")?;
+
+        match &statements[0] {
+            Stmt::Class(name, None, methods) => {
+                assert_eq!(name.lexeme, "A");
+                assert_eq!(methods.len(), 1);
+                assert!(matches!(
+                    &methods[0],
+                    Stmt::Function(_, _, body)
+                        if matches!(&body[0], Stmt::Expression(Expr::Set(object, name, _))
+                            if matches!(**object, Expr::This(_, None)) && name.lexeme == "x")
+                ));
+            }
+            _ => panic!("Expected a class declaration."),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid_property_assignment() {
+        assert!(matches!(
+            parse_source("a.b() = 1;"),
+            Err(RoxError::InvalidAssignmentError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_break_and_continue() -> Result<(), RoxError> {
+        let statements = parse_source("for (var i = 0; i < 3; i = i + 1) { continue; }")?;
+
+        // The increment stays separate from the body, so `continue` doesn't skip it.
+        match &statements[0] {
+            Stmt::Block(body) => assert!(matches!(
+                &body[1],
+                Stmt::While(_, loop_body, Some(Expr::Assign(..)))
+                    if matches!(&**loop_body, Stmt::Block(body) if matches!(body[0], Stmt::Continue(_)))
+            )),
+            stmt => panic!("Expected a desugared for loop, got {:?}.", stmt),
+        }
+
+        let tokens = Scanner::new("break;\nwhile (true) { fun f() { continue; } }".to_string())
+            .scan_tokens();
+        let (_, errors) = Parser::new(tokens).parse();
+        let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "1 at 'break' Can't use 'break' outside of a loop.",
+                "2 at 'continue' Can't use 'continue' outside of a loop.",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_try_statement() -> Result<(), RoxError> {
+        let statements =
+            parse_source("try { throw 1; } catch (e) { print e; } finally { print 2; }")?;
+
+        match &statements[0] {
+            Stmt::Try(_, body, Some((name, handler)), Some(finally)) => {
+                assert!(matches!(&body[0], Stmt::Throw(..)));
+                assert_eq!(name.lexeme, "e");
+                assert_eq!((handler.len(), finally.len()), (1, 1));
+            }
+            stmt => panic!("Expected a try statement, got {:?}.", stmt),
+        }
+        assert!(matches!(
+            &parse_source("try {} finally {}")?[0],
+            Stmt::Try(_, _, None, Some(_))
+        ));
+        assert!(parse_source("try {} print 1;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_reports_every_error() {
+        let tokens = Scanner::new(
+            "var = 1;\nprint (1;\nvar ok = 2;\n{ print 1 + ; var b = 3; }\n1 = 2;".to_string(),
+        )
+        .scan_tokens();
+        let (statements, errors) = Parser::new(tokens).parse();
+
+        let messages: Vec<String> = errors
+            .iter()
+            .map(|err| match err {
+                RoxError::ParseError(token, message) => format!("{} {}", token.line, message),
+                RoxError::InvalidAssignmentError(token) => {
+                    format!("{} Invalid assignment target.", token.line)
+                }
+                other => other.to_string(),
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "1 Expect variable name.",
+                "2 Expect ')' after expression.",
+                "4 Expect expression.",
+                "5 Invalid assignment target.",
+            ]
+        );
+
+        // The statements between the errors still parse, including the rest of the block.
+        assert_eq!(statements.len(), 3);
+        assert!(matches!(&statements[0], Stmt::Var(name, _) if name.lexeme == "ok"));
+        assert!(matches!(&statements[1], Stmt::Block(body) if body.len() == 1));
+    }
+
+    #[test]
+    fn test_parse_recovers_at_break_and_continue() {
+        let tokens =
+            Scanner::new("while (true) { print 1 2 break; print 3 4 continue; }".to_string())
+                .scan_tokens();
+        let (statements, errors) = Parser::new(tokens).parse();
+
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(
+            &statements[..],
+            [Stmt::While(_, body, None)] if matches!(
+                &**body,
+                Stmt::Block(body) if matches!(&body[..], [Stmt::Break(_), Stmt::Continue(_)])
+            )
+        ));
+    }
+
+    #[test]
+    fn test_parse_malformed_for_clauses() {
+        for (source, at) in [
+            ("for (var i = 0; i < ; i = i + 1) print i;", ";"),
+            ("for (var i = 0; i < 3; i = ) print i;", ")"),
+        ] {
+            let tokens = Scanner::new(source.to_string()).scan_tokens();
+            let (_, errors) = Parser::new(tokens).parse();
+
+            assert!(
+                matches!(
+                    errors.first(),
+                    Some(RoxError::ParseError(token, message))
+                        if token.lexeme == at && message == "Expect expression."
+                ),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_too_many_arguments() {
+        let args = vec!["1"; 256].join(", ");
+        let tokens = Scanner::new(format!("f({});", args)).scan_tokens();
+        let (statements, errors) = Parser::new(tokens).parse();
+
+        // The limit is reported without abandoning the call.
+        assert_eq!(statements.len(), 1);
+        assert!(matches!(
+            &errors[..],
+            [RoxError::ParseError(_, message)] if message == "Can't have more than 255 arguments."
+        ));
+    }
+
+    #[test]
+    fn test_parse_lists_and_indexing() -> Result<(), RoxError> {
+        let statements = parse_source("a[0] = [1, 2][1];")?;
+
+        match &statements[0] {
+            Stmt::Expression(Expr::SetIndex(object, bracket, _, value)) => {
+                assert!(matches!(&**object, Expr::Var(name, None) if name.lexeme == "a"));
+                assert_eq!(bracket.token_type, RightBracket);
+                assert!(matches!(
+                    &**value,
+                    Expr::Index(list, _, _) if matches!(&**list, Expr::List(_, elements) if elements.len() == 2)
+                ));
+            }
+            stmt => panic!("Expected an index assignment, got {:?}.", stmt),
+        }
+        assert!(parse_source("print [1, 2;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_lambdas() -> Result<(), RoxError> {
+        let statements = parse_source("var f = fun (a, b) { return a; };\nfun (x) {}(1);")?;
+
+        match &statements[0] {
+            Stmt::Var(_, Some(Expr::Lambda(keyword, params, body))) => {
+                assert_eq!(keyword.token_type, Fun);
+                assert_eq!(params.len(), 2);
+                assert!(matches!(&body[0], Stmt::Return(..)));
+            }
+            stmt => panic!("Expected a lambda, got {:?}.", stmt),
+        }
+        // A statement starting with `fun (` is an expression, not a declaration.
+        assert!(matches!(
+            &statements[1],
+            Stmt::Expression(Expr::Call(callee, _, _)) if matches!(&**callee, Expr::Lambda(..))
+        ));
+        assert!(parse_source("var f = fun a() {};").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_maps_and_blocks() -> Result<(), RoxError> {
+        let statements = parse_source("var m = {\"a\": 1, 2: {}};\n{ m; }")?;
+
+        match &statements[0] {
+            Stmt::Var(_, Some(Expr::Map(brace, entries))) => {
+                assert_eq!(brace.token_type, RightBrace);
+                assert_eq!(entries.len(), 2);
+                assert!(matches!(&entries[1].1, Expr::Map(_, inner) if inner.is_empty()));
+            }
+            stmt => panic!("Expected a map literal, got {:?}.", stmt),
+        }
+        // A `{` that starts a statement is still a block.
+        assert!(matches!(&statements[1], Stmt::Block(body) if body.len() == 1));
+        assert!(parse_source("print {\"a\" 1};").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_superclass_and_super_call() -> Result<(), RoxError> {
+        let statements = parse_source("class B < A { m() { super.m(); } }")?;
+
+        match &statements[0] {
+            Stmt::Class(_, Some(Expr::Var(superclass, None)), methods) => {
+                assert_eq!(superclass.lexeme, "A");
+                assert!(matches!(
+                    &methods[0],
+                    Stmt::Function(_, _, body)
+                        if matches!(&body[0], Stmt::Expression(Expr::Call(callee, _, _))
+                            if matches!(&**callee, Expr::Super(_, method, None) if method.lexeme == "m"))
+                ));
+            }
+            _ => panic!("Expected a subclass declaration."),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_return_statement() -> Result<(), RoxError> {
+        let statements = parse_source("fun f() { return 1; }")?;
+
+        assert!(matches!(
+            &statements[0],
+            Stmt::Function(_, _, body) if matches!(
+                &body[0],
+                Stmt::Return(_, Some(Expr::Literal(Literal::Number(n)))) if *n == 1.0
+            )
+        ));
         Ok(())
     }
 }
@@ -0,0 +1,225 @@
+use rox::error::ErrorHandler;
+use rox::scanner::Scanner;
+use rox::token::TokenType::{
+    LeftBrace, LeftBracket, LeftParen, RightBrace, RightBracket, RightParen,
+};
+use rox::Rox;
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use std::env;
+use std::path::PathBuf;
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = "... ";
+const HISTORY_FILE: &str = ".rox_history";
+
+const HELP: &str = "\
+Enter rox statements or expressions. Unfinished blocks, calls, lists and maps continue on
+the next line.
+
+Commands:
+  :help    Show this message.
+  :env     List the variables defined in the global scope.
+  :reset   Discard all definitions and start from a fresh interpreter.
+  :quit    Leave the REPL (Ctrl-D also works).";
+
+pub struct Repl {
+    rox: Rox,
+    buffer: String,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self {
+            rox: Rox::new(),
+            buffer: String::new(),
+        }
+    }
+
+    pub fn run(&mut self) {
+        let mut editor = Editor::<()>::new();
+        let history = history_path();
+        if let Some(path) = &history {
+            // A missing history file just means this is the first session.
+            let _ = editor.load_history(path);
+        }
+
+        loop {
+            let prompt = if self.buffer.is_empty() {
+                PROMPT
+            } else {
+                CONTINUATION_PROMPT
+            };
+
+            match editor.readline(prompt) {
+                Ok(line) => {
+                    editor.add_history_entry(line.as_str());
+                    if !self.read_line(&line) {
+                        break;
+                    }
+                }
+                Err(ReadlineError::Interrupted) => self.buffer.clear(),
+                Err(ReadlineError::Eof) => break,
+                Err(err) => {
+                    eprintln!("Error: {}", err);
+                    break;
+                }
+            }
+        }
+
+        if let Some(path) = &history {
+            if let Err(err) = editor.save_history(path) {
+                eprintln!("Could not save history: {}", err);
+            }
+        }
+    }
+
+    /// Feeds one line of input to the REPL. Returns `false` once the user asks to quit.
+    fn read_line(&mut self, line: &str) -> bool {
+        if self.buffer.is_empty() && line.trim_start().starts_with(':') {
+            return self.meta_command(line.trim());
+        }
+
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+
+        if !is_incomplete(&self.buffer) {
+            let source = std::mem::take(&mut self.buffer);
+            self.eval(&source);
+        }
+
+        true
+    }
+
+    fn meta_command(&mut self, command: &str) -> bool {
+        match command {
+            ":help" => println!("{}", HELP),
+            ":env" => {
+                for (name, value) in self.rox.globals() {
+                    println!("{} = {}", name, value);
+                }
+            }
+            ":reset" => {
+                self.rox = Rox::new();
+                println!("Environment reset.");
+            }
+            ":quit" => return false,
+            _ => println!("Unknown command '{}'. Type :help for help.", command),
+        }
+
+        true
+    }
+
+    fn eval(&mut self, source: &str) {
+        if source.trim().is_empty() {
+            return;
+        }
+
+        match self.rox.eval_line(source) {
+            Ok(Some(value)) => println!("{}", value),
+            Ok(None) => {}
+            // The session carries on after any error.
+            Err(errors) => {
+                let handler = ErrorHandler::new(source);
+                for err in &errors {
+                    handler.error(err);
+                }
+            }
+        }
+    }
+}
+
+/// Whether `source` still has an open `{` block, `(` call or `[` list that the next line should
+/// continue.
+fn is_incomplete(source: &str) -> bool {
+    let mut depth: i64 = 0;
+    for token in Scanner::new(source.to_string()).scan_tokens() {
+        match token.token_type {
+            LeftBrace | LeftParen | LeftBracket => depth += 1,
+            RightBrace | RightParen | RightBracket => depth -= 1,
+            _ => {}
+        }
+    }
+
+    depth > 0
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rox::Value;
+
+    fn global(repl: &Repl, name: &str) -> Option<Value> {
+        repl.rox.get_global(name)
+    }
+
+    #[test]
+    fn test_is_incomplete() {
+        assert!(is_incomplete("{"));
+        assert!(is_incomplete("fun f() {\n print 1;"));
+        assert!(is_incomplete("print clock("));
+        assert!(!is_incomplete("{ print 1; }"));
+        assert!(!is_incomplete("print 1;"));
+    }
+
+    #[test]
+    fn test_globals_persist_across_lines() {
+        let mut repl = Repl::new();
+        repl.read_line("var a = 1;");
+        repl.read_line("a = a + 1;");
+
+        assert_eq!(global(&repl, "a"), Some(Value::Number(2.0)));
+    }
+
+    #[test]
+    fn test_multi_line_block() {
+        let mut repl = Repl::new();
+        repl.read_line("var a = 1;");
+        repl.read_line("{");
+        repl.read_line("  a = 3;");
+        assert!(!repl.buffer.is_empty());
+
+        repl.read_line("}");
+        assert!(repl.buffer.is_empty());
+        assert_eq!(global(&repl, "a"), Some(Value::Number(3.0)));
+    }
+
+    #[test]
+    fn test_reset_meta_command() {
+        let mut repl = Repl::new();
+        repl.read_line("var a = 1;");
+        assert!(repl.read_line(":reset"));
+
+        assert_eq!(global(&repl, "a"), None);
+        assert!(global(&repl, "clock").is_some());
+    }
+
+    #[test]
+    fn test_quit_meta_command() {
+        let mut repl = Repl::new();
+
+        assert!(repl.read_line(":help"));
+        assert!(!repl.read_line(":quit"));
+    }
+
+    #[test]
+    fn test_runtime_error_keeps_session() {
+        let mut repl = Repl::new();
+        repl.read_line("var a = 1;");
+        repl.read_line("a = -\"oops\";");
+        repl.read_line("undefined;");
+        repl.read_line("a = a + 1;");
+
+        assert_eq!(global(&repl, "a"), Some(Value::Number(2.0)));
+    }
+}
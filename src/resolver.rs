@@ -0,0 +1,377 @@
+use crate::ast::{Expr, Stmt};
+use crate::error::RoxError;
+use crate::token::Token;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Initializer,
+    Method,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+/// Static pass run between `Parser::parse` and `Interpreter::interpret`. It records how many
+/// scopes separate every variable use from its declaration and reports misuse of scopes.
+pub struct Resolver {
+    // Each scope maps a name to whether its initializer has finished resolving.
+    scopes: Vec<HashMap<String, bool>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    errors: Vec<RoxError>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            scopes: Vec::new(),
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn resolve(mut self, statements: &mut [Stmt]) -> Result<(), Vec<RoxError>> {
+        self.resolve_stmts(statements);
+
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    fn resolve_stmts(&mut self, statements: &mut [Stmt]) {
+        for statement in statements {
+            self.resolve_stmt(statement);
+        }
+    }
+
+    fn resolve_stmt(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Block(statements) => {
+                self.begin_scope();
+                self.resolve_stmts(statements);
+                self.end_scope();
+            }
+            Stmt::Var(name, initializer) => {
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.resolve_expr(initializer);
+                }
+                self.define(name);
+            }
+            Stmt::Function(name, params, body) => {
+                self.declare(name);
+                self.define(name);
+                self.resolve_function(params, body, FunctionType::Function);
+            }
+            Stmt::Class(name, superclass, methods) => {
+                let enclosing_class = self.current_class;
+                self.current_class = ClassType::Class;
+
+                self.declare(name);
+                self.define(name);
+
+                if let Some(superclass) = superclass {
+                    self.current_class = ClassType::Subclass;
+                    self.resolve_expr(superclass);
+
+                    self.begin_scope();
+                    self.define_name("super");
+                }
+
+                self.begin_scope();
+                self.define_name("this");
+
+                for method in methods {
+                    if let Stmt::Function(method_name, params, body) = method {
+                        let function_type = if method_name.lexeme == "init" {
+                            FunctionType::Initializer
+                        } else {
+                            FunctionType::Method
+                        };
+                        self.resolve_function(params, body, function_type);
+                    }
+                }
+
+                self.end_scope();
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+
+                self.current_class = enclosing_class;
+            }
+            Stmt::Expression(expr) | Stmt::Print(_, expr) => self.resolve_expr(expr),
+            Stmt::If(condition, then_branch, else_branch) => {
+                self.resolve_expr(condition);
+                self.resolve_stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_stmt(else_branch);
+                }
+            }
+            Stmt::While(condition, body, increment) => {
+                self.resolve_expr(condition);
+                self.resolve_stmt(body);
+                if let Some(increment) = increment {
+                    self.resolve_expr(increment);
+                }
+            }
+            Stmt::Break(_) | Stmt::Continue(_) => {}
+            Stmt::Throw(_, value) => self.resolve_expr(value),
+            Stmt::Try(_, body, catch, finally) => {
+                self.begin_scope();
+                self.resolve_stmts(body);
+                self.end_scope();
+                // The exception variable shares a scope with the catch block's statements.
+                if let Some((name, handler)) = catch {
+                    self.begin_scope();
+                    self.declare(name);
+                    self.define(name);
+                    self.resolve_stmts(handler);
+                    self.end_scope();
+                }
+                if let Some(finally) = finally {
+                    self.begin_scope();
+                    self.resolve_stmts(finally);
+                    self.end_scope();
+                }
+            }
+            Stmt::Return(keyword, value) => {
+                if self.current_function == FunctionType::None {
+                    self.error(keyword, "Can't return from top-level code.");
+                }
+
+                if let Some(value) = value {
+                    if self.current_function == FunctionType::Initializer {
+                        self.error(keyword, "Can't return a value from an initializer.");
+                    }
+                    self.resolve_expr(value);
+                }
+            }
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Var(name, depth) => {
+                if let Some(false) = self.scopes.last().and_then(|scope| scope.get(&name.lexeme)) {
+                    self.error(name, "Can't read local variable in its own initializer.");
+                }
+                *depth = self.resolve_local(name);
+            }
+            Expr::Assign(name, value, depth) => {
+                self.resolve_expr(value);
+                *depth = self.resolve_local(name);
+            }
+            Expr::This(keyword, depth) => {
+                if self.current_class == ClassType::None {
+                    self.error(keyword, "Can't use 'this' outside of a class.");
+                }
+                *depth = self.resolve_local(keyword);
+            }
+            Expr::Super(keyword, _, depth) => {
+                match self.current_class {
+                    ClassType::None => self.error(keyword, "Can't use 'super' outside of a class."),
+                    ClassType::Class => {
+                        self.error(keyword, "Can't use 'super' in a class with no superclass.")
+                    }
+                    ClassType::Subclass => {}
+                }
+                *depth = self.resolve_local(keyword);
+            }
+            Expr::Binary(left, _, right) | Expr::Logical(left, _, right) => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            Expr::Call(callee, _, args) => {
+                self.resolve_expr(callee);
+                for arg in args {
+                    self.resolve_expr(arg);
+                }
+            }
+            Expr::Get(object, _) => self.resolve_expr(object),
+            Expr::Set(object, _, value) => {
+                self.resolve_expr(value);
+                self.resolve_expr(object);
+            }
+            Expr::List(_, elements) => {
+                for element in elements {
+                    self.resolve_expr(element);
+                }
+            }
+            Expr::Lambda(_, params, body) => {
+                self.resolve_function(params, body, FunctionType::Function)
+            }
+            Expr::Map(_, entries) => {
+                for (key, value) in entries {
+                    self.resolve_expr(key);
+                    self.resolve_expr(value);
+                }
+            }
+            Expr::Index(object, _, index) => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+            }
+            Expr::SetIndex(object, _, index, value) => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+                self.resolve_expr(value);
+            }
+            Expr::Grouping(expr) | Expr::Unary(_, expr) => self.resolve_expr(expr),
+            Expr::Literal(_) => {}
+        }
+    }
+
+    fn resolve_function(
+        &mut self,
+        params: &[Token],
+        body: &mut [Stmt],
+        function_type: FunctionType,
+    ) {
+        let enclosing_function = self.current_function;
+        self.current_function = function_type;
+
+        self.begin_scope();
+        for param in params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve_stmts(body);
+        self.end_scope();
+
+        self.current_function = enclosing_function;
+    }
+
+    /// Number of scopes between the innermost scope and the one declaring `name`,
+    /// or `None` if it isn't declared locally and must be a global.
+    fn resolve_local(&self, name: &Token) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains_key(&name.lexeme))
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token) {
+        let redeclared = match self.scopes.last_mut() {
+            Some(scope) => scope.insert(name.lexeme.clone(), false).is_some(),
+            None => false,
+        };
+
+        if redeclared {
+            self.error(name, "Already a variable with this name in this scope.");
+        }
+    }
+
+    fn define(&mut self, name: &Token) {
+        self.define_name(&name.lexeme);
+    }
+
+    fn define_name(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), true);
+        }
+    }
+
+    fn error(&mut self, token: &Token, message: &str) {
+        self.errors
+            .push(RoxError::ResolveError(token.clone(), message.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn resolve_source(source: &str) -> Result<Vec<Stmt>, Vec<RoxError>> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let (mut statements, errors) = Parser::new(tokens).parse();
+        assert!(errors.is_empty());
+        Resolver::new().resolve(&mut statements)?;
+        Ok(statements)
+    }
+
+    fn error_messages(source: &str) -> Vec<String> {
+        match resolve_source(source) {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors
+                .into_iter()
+                .map(|err| match err {
+                    RoxError::ResolveError(_, message) => message,
+                    other => other.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_resolve_local_depths() {
+        let statements = resolve_source("var a = 1; { var b = a; { b = 2; } }").unwrap();
+
+        let outer = match &statements[1] {
+            Stmt::Block(outer) => outer,
+            _ => panic!("Expected a block."),
+        };
+        assert!(matches!(&outer[0], Stmt::Var(_, Some(Expr::Var(_, None)))));
+        assert!(matches!(
+            &outer[1],
+            Stmt::Block(inner) if matches!(&inner[0], Stmt::Expression(Expr::Assign(_, _, Some(1))))
+        ));
+    }
+
+    #[test]
+    fn test_resolve_static_errors() {
+        assert_eq!(
+            error_messages("{ var a = a; }"),
+            vec!["Can't read local variable in its own initializer."]
+        );
+        assert_eq!(
+            error_messages("fun f() { var a; var a; }"),
+            vec!["Already a variable with this name in this scope."]
+        );
+        assert_eq!(
+            error_messages("return 1;"),
+            vec!["Can't return from top-level code."]
+        );
+        assert_eq!(
+            error_messages("class A { init() { return 1; } }"),
+            vec!["Can't return a value from an initializer."]
+        );
+        assert_eq!(
+            error_messages("print this;"),
+            vec!["Can't use 'this' outside of a class."]
+        );
+        assert_eq!(
+            error_messages("class A { m() { super.m(); } }"),
+            vec!["Can't use 'super' in a class with no superclass."]
+        );
+    }
+
+    #[test]
+    fn test_resolve_allows_global_redeclaration() {
+        assert!(error_messages("var a = 1; var a = a;").is_empty());
+    }
+}
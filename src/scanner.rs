@@ -3,24 +3,31 @@ use std::collections::HashMap;
 use std::str::FromStr;
 
 use crate::token::Literal;
+use crate::token::Span;
 use crate::token::Token;
 use crate::token::TokenType;
 use crate::token::TokenType::{
-    And, Bang, BangEqual, Class, Comma, Dot, Else, Eof, Equal, EqualEqual, False, For, Fun,
-    Greater, GreaterEqual, Identifier, If, LeftBrace, LeftParen, Less, LessEqual, Minus, Nil,
-    Number, Or, Plus, Print, Return, RightBrace, RightParen, Semicolon, Slash, Star, String_,
-    Super, This, True, Var, While,
+    And, Bang, BangEqual, Break, Catch, Class, Colon, Comma, Continue, Dot, Else, Eof, Equal,
+    EqualEqual, False, Finally, For, Fun, Greater, GreaterEqual, Identifier, If, LeftBrace,
+    LeftBracket, LeftParen, Less, LessEqual, Minus, Nil, Number, Or, Plus, Print, Return,
+    RightBrace, RightBracket, RightParen, Semicolon, Slash, Star, String_, Super, This, Throw,
+    True, Try, Var, While,
 };
 
 use crate::error::RoxError;
+use crate::intern::intern;
 
 lazy_static! {
     static ref KEYWORDS: HashMap<String, TokenType> = {
         let mut m = HashMap::new();
         m.insert("and".to_owned(), And);
+        m.insert("break".to_owned(), Break);
+        m.insert("catch".to_owned(), Catch);
         m.insert("class".to_owned(), Class);
+        m.insert("continue".to_owned(), Continue);
         m.insert("else".to_owned(), Else);
         m.insert("false".to_owned(), False);
+        m.insert("finally".to_owned(), Finally);
         m.insert("for".to_owned(), For);
         m.insert("fun".to_owned(), Fun);
         m.insert("if".to_owned(), If);
@@ -30,7 +37,9 @@ lazy_static! {
         m.insert("return".to_owned(), Return);
         m.insert("super".to_owned(), Super);
         m.insert("this".to_owned(), This);
+        m.insert("throw".to_owned(), Throw);
         m.insert("true".to_owned(), True);
+        m.insert("try".to_owned(), Try);
         m.insert("var".to_owned(), Var);
         m.insert("while".to_owned(), While);
         m
@@ -40,9 +49,15 @@ lazy_static! {
 pub struct Scanner {
     source: String,
     pub tokens: Vec<Token>,
+    pub errors: Vec<RoxError>,
     start: usize,
     current: usize,
     line: usize,
+    // Byte offset where the current line begins, for working out columns.
+    line_start: usize,
+    // Line and column of the token being scanned; strings can run past the line they start on.
+    start_line: usize,
+    start_column: usize,
 }
 
 impl Scanner {
@@ -50,29 +65,54 @@ impl Scanner {
         Self {
             source,
             tokens: Vec::new(),
+            errors: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
         }
     }
     pub fn scan_tokens(&mut self) -> Vec<Token> {
         while !self.is_at_end() {
-            self.start = self.current;
+            self.begin_token();
             self.scan_token();
         }
 
-        self.tokens.push(Token::new(Eof, "", None, self.line));
+        self.begin_token();
+        self.add_token(Eof);
 
         self.tokens.clone()
     }
 
     fn is_at_end(&self) -> bool {
-        return self.current >= self.source.len();
+        self.current >= self.source.len()
+    }
+
+    fn begin_token(&mut self) {
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.source[self.line_start..self.start].chars().count() + 1;
+    }
+
+    fn span(&self) -> Span {
+        Span {
+            start: self.start,
+            end: self.current,
+            column: self.start_column,
+        }
+    }
+
+    fn newline(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
     }
 
     fn advance(&mut self) -> char {
-        self.current += 1;
-        self.source.chars().nth(self.current - 1).unwrap()
+        let c = self.peek();
+        self.current += c.len_utf8();
+        c
     }
 
     fn add_token(&mut self, token_type: TokenType) {
@@ -84,8 +124,9 @@ impl Scanner {
             token_type,
             &self.source[self.start..self.current],
             literal,
-            self.line,
-        );
+            self.start_line,
+        )
+        .with_span(self.span());
         self.tokens.push(token);
     }
 
@@ -97,6 +138,9 @@ impl Scanner {
             ')' => self.add_token(RightParen),
             '{' => self.add_token(LeftBrace),
             '}' => self.add_token(RightBrace),
+            '[' => self.add_token(LeftBracket),
+            ']' => self.add_token(RightBracket),
+            ':' => self.add_token(Colon),
             ',' => self.add_token(Comma),
             '.' => self.add_token(Dot),
             '-' => self.add_token(Minus),
@@ -145,22 +189,15 @@ impl Scanner {
                 };
             }
             ' ' | '\r' | '\t' => {}
-            '\n' => {
-                self.line += 1;
-            }
+            '\n' => self.newline(),
             '"' => self.string(),
-            'o' => {
-                if self.match_char('r') {
-                    self.add_token(Or);
-                }
-            }
             _ => {
                 if self.is_digit(c) {
                     self.number()
                 } else if self.is_alphanumeric(c) {
                     self.identifier()
                 } else {
-                    RoxError::UnexpectedCharacterError(self.line.to_string());
+                    self.error("Unexpected character.");
                 }
             }
         }
@@ -171,11 +208,11 @@ impl Scanner {
             return false;
         }
 
-        if self.source.chars().nth(self.current).unwrap() == expected {
-            self.current += 1;
-            return true;
+        if self.peek() == expected {
+            self.current += expected.len_utf8();
+            true
         } else {
-            return false;
+            false
         }
     }
 
@@ -183,31 +220,30 @@ impl Scanner {
         if self.is_at_end() {
             '\0'
         } else {
-            self.source.chars().nth(self.current).unwrap()
+            self.source[self.current..].chars().next().unwrap()
         }
     }
 
     fn string(&mut self) {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            if self.advance() == '\n' {
+                self.newline();
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            RoxError::UnexpectedCharacterError(self.line.to_string());
+            self.error("Unterminated string.");
+            return;
         }
 
         self.advance();
 
-        let value = self.source[self.start + 1..self.current - 1].to_owned();
-        let literal = Literal::String_(value);
+        let literal = Literal::String_(intern(&self.source[self.start + 1..self.current - 1]));
         self.add_token_with_literal(String_, Some(literal));
     }
 
     fn is_digit(&self, c: char) -> bool {
-        c.is_digit(10)
+        c.is_ascii_digit()
     }
 
     fn number(&mut self) {
@@ -219,7 +255,7 @@ impl Scanner {
             // Consume the "."
             self.advance();
 
-            while self.peek().is_digit(10) {
+            while self.peek().is_ascii_digit() {
                 self.advance();
             }
         }
@@ -246,11 +282,15 @@ impl Scanner {
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            return '\0';
-        }
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
+    }
 
-        self.source.chars().nth(self.current + 1).unwrap()
+    fn error(&mut self, message: &str) {
+        self.errors.push(RoxError::ScanError(
+            self.start_line,
+            self.span(),
+            message.to_string(),
+        ));
     }
 }
 
@@ -263,7 +303,7 @@ mod tests {
         let mut scanner = Scanner::new("print 'Hello, world!'".to_string());
         scanner.scan_tokens();
 
-        let expected_tokens = vec![
+        let expected_tokens = [
             Token::new(Print, "print", None, 1),
             Token::new(Identifier, "Hello", None, 1),
             Token::new(Comma, ",", None, 1),
@@ -273,8 +313,8 @@ mod tests {
         ];
         assert!(scanner.tokens.len() == 6);
 
-        for i in 0..scanner.tokens.len() {
-            assert!(scanner.tokens[i] == expected_tokens[i]);
+        for (token, expected_token) in scanner.tokens.iter().zip(expected_tokens.iter()) {
+            assert!(token == expected_token);
         }
     }
 
@@ -309,17 +349,70 @@ mod tests {
     fn test_number() {
         let mut scanner = Scanner::new("314 == 'pi'".to_string());
         scanner.scan_tokens();
-        let expected_tokens = vec![
-            Token::new(Number, "314", Some(Literal::Number(1.0)), 1),
+        let expected_tokens = [
+            Token::new(Number, "314", Some(Literal::Number(314.0)), 1),
             Token::new(EqualEqual, "==", None, 1),
             Token::new(Identifier, "pi", None, 1),
             Token::new(Eof, "", None, 1),
         ];
-        for i in 0..scanner.tokens.len() {
-            assert!(scanner.tokens[i] == expected_tokens[i]);
+        for (token, expected_token) in scanner.tokens.iter().zip(expected_tokens.iter()) {
+            assert!(token == expected_token);
         }
     }
 
+    #[test]
+    fn test_token_spans() {
+        let mut scanner = Scanner::new("var s = \"é\";\n  print s;".to_string());
+        let tokens = scanner.scan_tokens();
+
+        let string = &tokens[3];
+        assert_eq!(string.token_type, String_);
+        assert_eq!((string.span.start, string.span.end), (8, 12));
+        assert_eq!(string.span.column, 9);
+
+        // Columns count characters, so the two-byte 'é' only moves the ';' one column along.
+        assert_eq!(tokens[4].span.column, 12);
+
+        let print = &tokens[5];
+        assert_eq!(print.line, 2);
+        assert_eq!(print.span.column, 3);
+        assert_eq!(&scanner.source[print.span.start..print.span.end], "print");
+    }
+
+    #[test]
+    fn test_string_literals_are_interned() {
+        let tokens = Scanner::new("\"hi\" \"hi\"".to_string()).scan_tokens();
+
+        match (&tokens[0].literal, &tokens[1].literal) {
+            (Some(Literal::String_(a)), Some(Literal::String_(b))) => {
+                assert!(std::rc::Rc::ptr_eq(a, b))
+            }
+            literals => panic!("Expected two strings, got {:?}.", literals),
+        }
+    }
+
+    #[test]
+    fn test_scan_errors() {
+        let mut scanner = Scanner::new("var a = 1;\n  @\n\"open".to_string());
+        scanner.scan_tokens();
+
+        let errors: Vec<(usize, usize, String)> = scanner
+            .errors
+            .iter()
+            .map(|err| match err {
+                RoxError::ScanError(line, span, message) => (*line, span.column, message.clone()),
+                other => panic!("Unexpected error {}", other),
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (2, 3, "Unexpected character.".to_string()),
+                (3, 1, "Unterminated string.".to_string()),
+            ]
+        );
+    }
+
     #[test]
     fn test_is_at_end() {
         let scanner = Scanner::new("".to_string());
@@ -1,3 +1,5 @@
+use std::rc::Rc;
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum TokenType {
     // Single-character tokens.
@@ -5,6 +7,9 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -30,9 +35,13 @@ pub enum TokenType {
 
     // Keywords.
     And,
+    Break,
+    Catch,
     Class,
+    Continue,
     Else,
     False,
+    Finally,
     Fun,
     For,
     If,
@@ -42,19 +51,31 @@ pub enum TokenType {
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
     Eof,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// Where a token sits in its source: byte offsets of its first and one-past-last bytes, and
+/// the 1-based column of its first character on `Token::line`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub column: usize,
+}
+
+#[derive(Clone, Debug)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub literal: Option<Literal>,
     pub line: usize,
+    pub span: Span,
 }
 
 impl Token {
@@ -64,13 +85,30 @@ impl Token {
             lexeme: lexeme.to_owned(),
             literal,
             line,
+            span: Span::default(),
         }
     }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
+}
+
+// Spans only feed diagnostics, so tokens (and the syntax trees holding them) compare by what
+// they say rather than by exactly where they were written.
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.token_type == other.token_type
+            && self.lexeme == other.lexeme
+            && self.literal == other.literal
+            && self.line == other.line
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
-    String_(String),
+    String_(Rc<str>),
     Number(f64),
     Bool(bool),
     Nil,
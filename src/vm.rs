@@ -0,0 +1,997 @@
+use crate::ast::Value;
+use crate::chunk::{Constant, Function, OpCode};
+use crate::class::{exception_value, Method, RoxClass, RoxInstance};
+use crate::error::{RoxError, StackFrame, StackTrace};
+use crate::function::RoxFunction;
+use crate::gc::{self, Heap, Trace};
+use crate::intern::intern;
+use crate::map::{Key, Map};
+use crate::native::{self, Arity};
+use crate::output::Output;
+use crate::token::Token;
+use crate::token::TokenType::Identifier;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// How deep calls can nest before the VM gives up with a stack overflow.
+const FRAMES_MAX: usize = 256;
+
+/// A variable captured by a closure. It points into the stack while the variable's scope
+/// is live and holds the value itself once the scope has ended.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    // For a bound method, the instance it goes into slot zero with.
+    pub receiver: Option<Value>,
+}
+
+impl Trace for Upvalue {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        if let Upvalue::Closed(value) = self {
+            value.trace(visit);
+        }
+    }
+
+    fn clear(&mut self) {
+        *self = Upvalue::Closed(Value::Nil);
+    }
+}
+
+impl Trace for Closure {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        for upvalue in &self.upvalues {
+            visit(gc::address(upvalue));
+        }
+        if let Some(receiver) = &self.receiver {
+            receiver.trace(visit);
+        }
+    }
+}
+
+/// Where to go when an error happens between a `Catch` or `Finally` and its `EndTry`.
+struct Handler {
+    // The frame that started it, and the height of the stack then.
+    frame: usize,
+    stack: usize,
+    target: usize,
+    finally: bool,
+    // Set while a `finally` block runs because of an error, for `Rethrow` to raise again.
+    error: Option<RoxError>,
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    // Stack index of the frame's slot zero.
+    slots: usize,
+}
+
+/// Stack-based virtual machine running code from the `compiler`.
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<Rc<str>, Value>,
+    // Upvalues still pointing into the stack, ordered by slot.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    handlers: Vec<Handler>,
+    pub heap: Heap,
+    pub output: Output,
+    // The class of the values that runtime errors become when caught.
+    error_class: Rc<RoxClass>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        let mut globals = HashMap::new();
+        for function in native::standard_library() {
+            globals.insert(intern(function.name()), Value::Callable(function));
+        }
+
+        let mut heap = Heap::new();
+        let error_class = heap.class(RoxClass::new("Error".to_string(), None, HashMap::new()));
+        Self {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals,
+            open_upvalues: Vec::new(),
+            handlers: Vec::new(),
+            heap,
+            output: Output::default(),
+            error_class,
+        }
+    }
+
+    /// Defines a global function that runs `body`, which is passed the call's arguments once
+    /// their count has been checked against `arity`.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        body: impl Fn(&[Value]) -> Result<Value, RoxError> + 'static,
+    ) {
+        let function = RoxFunction::native(name, arity, body);
+        self.globals.insert(intern(name), Value::Callable(function));
+    }
+
+    /// Runs a compiled script until it finishes or hits its first runtime error. Globals
+    /// persist between calls.
+    pub fn interpret(&mut self, script: Rc<Function>) -> Result<(), RoxError> {
+        self.evaluate(script).map(|_| ())
+    }
+
+    /// Like `interpret`, but hands back what the script returns: the value of the expression
+    /// in a script from `Compiler::compile_expression`.
+    pub fn evaluate(&mut self, script: Rc<Function>) -> Result<Value, RoxError> {
+        let closure = self.heap.closure(Closure {
+            function: script,
+            upvalues: Vec::new(),
+            receiver: None,
+        });
+        self.stack.push(Value::Closure(Rc::clone(&closure)));
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slots: 0,
+        });
+
+        self.run(0)
+    }
+
+    /// Calls a function or closure from outside a script, once one has defined it.
+    pub fn call(&mut self, callee: Value, arguments: &[Value]) -> Result<Value, RoxError> {
+        let base = self.frames.len();
+        self.stack.push(callee.clone());
+        self.stack.extend_from_slice(arguments);
+        if let Err(err) = self.call_value(callee, arguments.len()) {
+            self.reset();
+            return Err(err);
+        }
+
+        if self.frames.len() == base {
+            // A native, which has already left its result on the stack.
+            return Ok(self.pop());
+        }
+        self.run(base)
+    }
+
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.globals.get(name).cloned()
+    }
+
+    /// Every global, sorted by name.
+    pub fn globals(&self) -> Vec<(Rc<str>, Value)> {
+        let mut globals: Vec<(Rc<str>, Value)> = self
+            .globals
+            .iter()
+            .map(|(name, value)| (Rc::clone(name), value.clone()))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(intern(name), value);
+    }
+
+    /// Runs until the frame `base` frames up from the bottom returns, then hands back what it
+    /// returned. An error goes to the innermost `try` handling it, or else unwinds everything.
+    fn run(&mut self, base: usize) -> Result<Value, RoxError> {
+        loop {
+            let err = match self.execute(base) {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            if let Err(err) = self.unwind(err, base) {
+                self.reset();
+                return Err(err);
+            }
+        }
+    }
+
+    /// Unwinds to the innermost handler at or above frame `base` and jumps to its `catch`
+    /// clause or `finally` block. Hands `err` back if there is none.
+    fn unwind(&mut self, err: RoxError, base: usize) -> Result<(), RoxError> {
+        while let Some(handler) = self.handlers.pop() {
+            if handler.frame < base {
+                self.handlers.push(handler);
+                break;
+            }
+            // A `finally` block that fails replaces the error it was running for.
+            if handler.error.is_some() {
+                continue;
+            }
+
+            self.frames.truncate(handler.frame + 1);
+            self.close_upvalues(handler.stack);
+            self.stack.truncate(handler.stack);
+            self.frame_mut().ip = handler.target;
+            if handler.finally {
+                self.handlers.push(Handler {
+                    error: Some(err),
+                    ..handler
+                });
+            } else {
+                let exception = exception_value(err, &self.error_class, &mut self.heap)?;
+                self.stack.push(exception);
+            }
+            return Ok(());
+        }
+        Err(err)
+    }
+
+    fn reset(&mut self) {
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+        self.handlers.clear();
+    }
+
+    fn execute(&mut self, base: usize) -> Result<Value, RoxError> {
+        loop {
+            let frame = self.frames.last_mut().expect("A function is running.");
+            let op = frame.closure.function.chunk.code[frame.ip];
+            frame.ip += 1;
+
+            match op {
+                OpCode::Constant(index) => {
+                    let value = match self.constant(index) {
+                        Constant::Value(value) => value.clone(),
+                        Constant::Function(_) => unreachable!("Functions load with Closure."),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Bool(true)),
+                OpCode::False => self.stack.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal(slot) => {
+                    let value = self.stack[self.frame().slots + slot as usize].clone();
+                    self.stack.push(value);
+                }
+                OpCode::SetLocal(slot) => {
+                    let slot = self.frame().slots + slot as usize;
+                    self.stack[slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal(index) => {
+                    let name = self.constant_name(index);
+                    match self.globals.get(&name) {
+                        Some(value) => {
+                            let value = value.clone();
+                            self.stack.push(value);
+                        }
+                        None => return Err(self.undefined_variable(&name)),
+                    }
+                }
+                OpCode::DefineGlobal(index) => {
+                    let name = self.constant_name(index);
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal(index) => {
+                    let name = self.constant_name(index);
+                    if !self.globals.contains_key(&name) {
+                        return Err(self.undefined_variable(&name));
+                    }
+                    let value = self.peek(0).clone();
+                    self.globals.insert(name, value);
+                }
+                OpCode::GetUpvalue(index) => {
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[index as usize]);
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue(index) => {
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[index as usize]);
+                    let value = self.peek(0).clone();
+                    let mut upvalue = upvalue.borrow_mut();
+                    match &mut *upvalue {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::Equal => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(Value::Bool(left.equals(&right)));
+                }
+                OpCode::Greater => self.comparison(|l, r| l > r)?,
+                OpCode::GreaterEqual => self.comparison(|l, r| l >= r)?,
+                OpCode::Less => self.comparison(|l, r| l < r)?,
+                OpCode::LessEqual => self.comparison(|l, r| l <= r)?,
+                OpCode::Add => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let value = match (left, right) {
+                        (Value::Number(l), Value::Number(r)) => Value::Number(l + r),
+                        (Value::String_(l), Value::String_(r)) => {
+                            Value::String_([&*l, &*r].concat().into())
+                        }
+                        _ => {
+                            return Err(
+                                self.runtime_error("Operands must be two numbers or two strings.")
+                            )
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Subtract => self.arithmetic(|l, r| l - r)?,
+                OpCode::Multiply => self.arithmetic(|l, r| l * r)?,
+                OpCode::Divide => self.arithmetic(|l, r| l / r)?,
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Bool(!is_truthy(&value)));
+                }
+                OpCode::Negate => match self.pop() {
+                    Value::Number(n) => self.stack.push(Value::Number(-n)),
+                    _ => return Err(self.runtime_error("Operand must be a number.")),
+                },
+                OpCode::Print => {
+                    let value = self.pop();
+                    self.output.print(&value).map_err(|err| {
+                        self.runtime_error(&format!("Could not write output: {}", err))
+                    })?;
+                }
+                OpCode::Jump(distance) => self.frame_mut().ip += distance as usize,
+                OpCode::JumpIfFalse(distance) => {
+                    if !is_truthy(self.peek(0)) {
+                        self.frame_mut().ip += distance as usize;
+                    }
+                }
+                OpCode::Loop(distance) => self.frame_mut().ip -= distance as usize,
+                OpCode::Call(arg_count) => {
+                    let callee = self.peek(arg_count as usize).clone();
+                    self.call_value(callee, arg_count as usize)?;
+                }
+                OpCode::BuildList(count) => {
+                    let elements = self.stack.split_off(self.stack.len() - count as usize);
+                    let list = self.heap.list(elements);
+                    self.stack.push(Value::List(list));
+                }
+                OpCode::BuildMap(count) => {
+                    let entries = self.stack.split_off(self.stack.len() - 2 * count as usize);
+                    let mut map = Map::new();
+                    for entry in entries.chunks(2) {
+                        let key = Key::try_from(&entry[0])
+                            .map_err(|message| self.runtime_error(&message))?;
+                        map.insert(key, entry[1].clone());
+                    }
+                    let map = self.heap.map(map);
+                    self.stack.push(Value::Map(map));
+                }
+                OpCode::Index => {
+                    let index = self.pop();
+                    let value = self
+                        .pop()
+                        .get_index(&index)
+                        .map_err(|message| self.runtime_error(&message))?;
+                    self.stack.push(value);
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    self.pop()
+                        .set_index(&index, value.clone())
+                        .map_err(|message| self.runtime_error(&message))?;
+                    self.stack.push(value);
+                }
+                OpCode::Closure(index) => {
+                    let function = match self.constant(index) {
+                        Constant::Function(function) => Rc::clone(function),
+                        Constant::Value(_) => unreachable!("Closure always wraps a function."),
+                    };
+                    let upvalues = function
+                        .upvalues
+                        .iter()
+                        .map(|upvalue| {
+                            if upvalue.is_local {
+                                self.capture_upvalue(self.frame().slots + upvalue.index as usize)
+                            } else {
+                                Rc::clone(&self.frame().closure.upvalues[upvalue.index as usize])
+                            }
+                        })
+                        .collect();
+                    let closure = self.heap.closure(Closure {
+                        function,
+                        upvalues,
+                        receiver: None,
+                    });
+                    self.stack.push(Value::Closure(closure));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("A function is running.");
+                    self.close_upvalues(frame.slots);
+
+                    // Pops the arguments and the callee, or the script itself.
+                    self.stack.truncate(frame.slots);
+                    if self.frames.len() == base {
+                        return Ok(result);
+                    }
+                    self.stack.push(result);
+                }
+                OpCode::Class(index, count) | OpCode::Subclass(index, count) => {
+                    let name = self.constant_name(index);
+                    let closures = self.stack.split_off(self.stack.len() - count as usize);
+                    let superclass = match op {
+                        OpCode::Subclass(..) => match self.peek(0) {
+                            Value::Class(superclass) => Some(Rc::clone(superclass)),
+                            _ => return Err(self.runtime_error("Superclass must be a class.")),
+                        },
+                        _ => None,
+                    };
+
+                    let methods = closures
+                        .into_iter()
+                        .map(|closure| match closure {
+                            Value::Closure(closure) => {
+                                (closure.function.name.clone(), Method::Closure(closure))
+                            }
+                            value => unreachable!("{} is not a method.", value),
+                        })
+                        .collect();
+                    let class =
+                        self.heap
+                            .class(RoxClass::new(name.to_string(), superclass, methods));
+                    self.stack.push(Value::Class(class));
+                }
+                OpCode::GetProperty(index) => {
+                    let name = self.constant_name(index);
+                    let value = match self.pop() {
+                        Value::Instance(instance) => {
+                            RoxInstance::get(&instance, &name, &mut self.heap).ok_or_else(|| {
+                                self.runtime_error(&format!("Undefined property '{}'.", name))
+                            })?
+                        }
+                        _ => return Err(self.runtime_error("Only instances have properties.")),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetProperty(index) => {
+                    let name = self.constant_name(index);
+                    let value = self.pop();
+                    match self.pop() {
+                        Value::Instance(instance) => {
+                            instance.borrow_mut().set(&name, value.clone());
+                        }
+                        _ => return Err(self.runtime_error("Only instances have fields.")),
+                    }
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper(index) => {
+                    let name = self.constant_name(index);
+                    let superclass = match self.pop() {
+                        Value::Class(superclass) => superclass,
+                        _ => {
+                            return Err(self
+                                .runtime_error("Can't use 'super' in a class with no superclass."))
+                        }
+                    };
+                    let this = self.pop();
+                    let method = superclass.find_method(&name).ok_or_else(|| {
+                        self.runtime_error(&format!("Undefined property '{}'.", name))
+                    })?;
+                    let method = method.bind(this, &mut self.heap);
+                    self.stack.push(method);
+                }
+                OpCode::Fail(index) => {
+                    let message = match self.constant(index) {
+                        Constant::Value(Value::String_(message)) => message.clone(),
+                        constant => unreachable!("{:?} is not a message.", constant),
+                    };
+                    return Err(self.runtime_error(&message));
+                }
+                OpCode::Throw => {
+                    let value = self.pop();
+                    return Err(RoxError::Throw(Box::new(value), self.token(), self.trace()));
+                }
+                OpCode::Catch(distance) | OpCode::Finally(distance) => {
+                    let handler = Handler {
+                        frame: self.frames.len() - 1,
+                        stack: self.stack.len(),
+                        target: self.frame().ip + distance as usize,
+                        finally: matches!(op, OpCode::Finally(_)),
+                        error: None,
+                    };
+                    self.handlers.push(handler);
+                }
+                OpCode::EndTry => {
+                    self.handlers.pop();
+                }
+                OpCode::Rethrow => {
+                    let handler = self.handlers.pop().expect("Rethrow ends a `Finally`.");
+                    return Err(handler
+                        .error
+                        .expect("Only a `finally` block run for an error ends in `Rethrow`."));
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<(), RoxError> {
+        match callee {
+            Value::Closure(closure) => {
+                if arg_count != closure.function.arity {
+                    return Err(self.runtime_error(&format!(
+                        "Expected {} arguments but got {}.",
+                        closure.function.arity, arg_count
+                    )));
+                }
+                if self.frames.len() == FRAMES_MAX {
+                    return Err(self.runtime_error("Stack overflow."));
+                }
+
+                let slots = self.stack.len() - arg_count - 1;
+                if let Some(receiver) = &closure.receiver {
+                    self.stack[slots] = receiver.clone();
+                }
+                self.frames.push(CallFrame {
+                    closure,
+                    ip: 0,
+                    slots,
+                });
+                Ok(())
+            }
+            Value::Class(class) => {
+                let initializer = class.find_method("init");
+                let arity = initializer.as_ref().map_or(Arity::Fixed(0), Method::arity);
+                arity
+                    .check(arg_count)
+                    .map_err(|message| self.runtime_error(&message))?;
+
+                // The instance takes the class's place, which is where a call leaves its result.
+                let instance = Value::Instance(self.heap.instance(RoxInstance::new(class)));
+                let slot = self.stack.len() - arg_count - 1;
+                self.stack[slot] = instance.clone();
+                match initializer {
+                    Some(initializer) => {
+                        let initializer = initializer.bind(instance, &mut self.heap);
+                        self.call_value(initializer, arg_count)
+                    }
+                    None => Ok(()),
+                }
+            }
+            Value::Callable(RoxFunction::Native { arity, body, .. }) => {
+                arity
+                    .check(arg_count)
+                    .map_err(|message| self.runtime_error(&message))?;
+
+                let arguments = self.stack.split_off(self.stack.len() - arg_count);
+                self.pop();
+                let value = body(&arguments).map_err(|err| match err {
+                    RoxError::NativeError(message) => self.runtime_error(&message),
+                    err => err,
+                })?;
+                self.heap.adopt(&value);
+                self.stack.push(value);
+                Ok(())
+            }
+            _ => Err(self.runtime_error("Can only call functions and classes.")),
+        }
+    }
+
+    /// Returns the upvalue for stack slot `slot`, sharing it with any closure that has
+    /// already captured the same variable.
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let position = self
+            .open_upvalues
+            .iter()
+            .position(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open) if open >= slot));
+
+        if let Some(position) = position {
+            let existing = &self.open_upvalues[position];
+            if matches!(*existing.borrow(), Upvalue::Open(open) if open == slot) {
+                return Rc::clone(existing);
+            }
+        }
+
+        let upvalue = self.heap.upvalue(Upvalue::Open(slot));
+        let position = position.unwrap_or(self.open_upvalues.len());
+        self.open_upvalues.insert(position, Rc::clone(&upvalue));
+        upvalue
+    }
+
+    /// Moves every captured variable at or above stack slot `last` off the stack.
+    fn close_upvalues(&mut self, last: usize) {
+        while let Some(upvalue) = self.open_upvalues.last() {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) if slot >= last => slot,
+                _ => break,
+            };
+            *upvalue.borrow_mut() = Upvalue::Closed(self.stack[slot].clone());
+            self.open_upvalues.pop();
+        }
+    }
+
+    fn arithmetic(&mut self, op: fn(f64, f64) -> f64) -> Result<(), RoxError> {
+        let (left, right) = self.number_operands()?;
+        self.stack.push(Value::Number(op(left, right)));
+        Ok(())
+    }
+
+    fn comparison(&mut self, op: fn(f64, f64) -> bool) -> Result<(), RoxError> {
+        let (left, right) = self.number_operands()?;
+        self.stack.push(Value::Bool(op(left, right)));
+        Ok(())
+    }
+
+    fn number_operands(&mut self) -> Result<(f64, f64), RoxError> {
+        let right = self.pop();
+        let left = self.pop();
+        match (left, right) {
+            (Value::Number(left), Value::Number(right)) => Ok((left, right)),
+            _ => Err(self.runtime_error("Operands must be numbers.")),
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("A function is running.")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("A function is running.")
+    }
+
+    fn constant(&self, index: u16) -> &Constant {
+        &self.frame().closure.function.chunk.constants[index as usize]
+    }
+
+    fn constant_name(&self, index: u16) -> Rc<str> {
+        match self.constant(index) {
+            Constant::Value(Value::String_(name)) => name.clone(),
+            constant => unreachable!("{:?} is not a variable name.", constant),
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack
+            .pop()
+            .expect("The compiler keeps the stack balanced.")
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    /// The token the running instruction was compiled from.
+    fn token(&self) -> Token {
+        let frame = self.frames.last().expect("An instruction is running.");
+        frame
+            .closure
+            .function
+            .chunk
+            .token(frame.ip - 1)
+            .expect("Every instruction is compiled from a token.")
+            .clone()
+    }
+
+    fn runtime_error(&self, message: &str) -> RoxError {
+        if self.frames.is_empty() {
+            // The host's call failed before anything started running.
+            return RoxError::HostCallError(message.to_string());
+        }
+        RoxError::RuntimeError(self.token(), message.to_string(), self.trace())
+    }
+
+    fn undefined_variable(&self, name: &str) -> RoxError {
+        let mut token = self.token();
+        if token.lexeme != name {
+            token = Token::new(Identifier, name, None, token.line);
+        }
+        RoxError::UndefinedVariableError(token, self.trace())
+    }
+
+    /// The functions running, innermost first, each with the line it was called from. The
+    /// script itself isn't a call, so it is left out.
+    fn trace(&self) -> StackTrace {
+        Box::new(
+            self.frames
+                .iter()
+                .enumerate()
+                .rev()
+                .filter(|(_, frame)| !frame.closure.function.name.is_empty())
+                .map(|(i, frame)| StackFrame {
+                    function: frame.closure.function.name.clone(),
+                    // A function the host called has no frame below it.
+                    line: i.checked_sub(1).map(|caller| {
+                        let caller = &self.frames[caller];
+                        caller.closure.function.chunk.line(caller.ip - 1)
+                    }),
+                })
+                .collect(),
+        )
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    !matches!(value, Value::Nil | Value::Bool(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn run_source(source: &str) -> (Vm, Result<(), RoxError>) {
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let (statements, errors) = Parser::new(tokens).parse();
+        assert!(errors.is_empty());
+        let script = Compiler::new().compile(&statements).unwrap();
+
+        let mut vm = Vm::new();
+        let result = vm.interpret(script);
+        (vm, result)
+    }
+
+    fn global(vm: &Vm, name: &str) -> Option<Value> {
+        vm.globals.get(name).cloned()
+    }
+
+    #[test]
+    fn test_arithmetic_and_globals() {
+        let (vm, result) = run_source("var a = 1 + 2 * 3; var b = \"a\" + \"b\"; a = -a;");
+
+        assert!(result.is_ok());
+        assert_eq!(global(&vm, "a"), Some(Value::Number(-7.0)));
+        assert_eq!(global(&vm, "b"), Some(Value::String_("ab".into())));
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let (vm, _) = run_source(
+            "var total = 0;
+             for (var i = 0; i < 5; i = i + 1) { if (i == 3 or i == 4) total = total + i; }
+             var none = nil and 1;",
+        );
+
+        assert_eq!(global(&vm, "total"), Some(Value::Number(7.0)));
+        assert_eq!(global(&vm, "none"), Some(Value::Nil));
+    }
+
+    #[test]
+    fn test_recursion() {
+        let (vm, _) = run_source(
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+             var result = fib(15);",
+        );
+
+        assert_eq!(global(&vm, "result"), Some(Value::Number(610.0)));
+    }
+
+    #[test]
+    fn test_closures_share_captured_variables() {
+        let (vm, _) = run_source(
+            "fun counter() {
+               var count = 0;
+               fun increment() { count = count + 1; return count; }
+               fun get() { return count; }
+               increment(); increment();
+               return get;
+             }
+             var get = counter();
+             var count = get();",
+        );
+
+        assert_eq!(global(&vm, "count"), Some(Value::Number(2.0)));
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let (mut vm, result) = run_source(
+            "var total = 0;
+            var last;
+            for (var i = 0; i < 10; i = i + 1) {
+                var doubled = i * 2;
+                if (i == 1) continue;
+                fun get() { return doubled; }
+                last = get;
+                if (i == 4) { var unused = 0; break; }
+                total = total + doubled;
+            }
+            var after = 1;",
+        );
+
+        assert!(result.is_ok());
+        // The locals the jumps skipped past are gone, so globals still line up.
+        assert_eq!(global(&vm, "total"), Some(Value::Number(10.0)));
+        assert_eq!(global(&vm, "after"), Some(Value::Number(1.0)));
+        assert_eq!(
+            vm.call(global(&vm, "last").unwrap(), &[]).unwrap(),
+            Value::Number(8.0)
+        );
+    }
+
+    #[test]
+    fn test_lambdas() {
+        let (vm, result) = run_source(
+            "fun adder(n) { return fun (x) { return x + n; }; }
+            var add2 = adder(2);
+            var a = add2(add2(1));
+            var b = fun (f) { return f(); }(fun () { return add2; });",
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(global(&vm, "a"), Some(Value::Number(5.0)));
+        assert_eq!(global(&vm, "b").unwrap().to_string(), "<fn lambda>");
+    }
+
+    #[test]
+    fn test_lists() {
+        let (vm, result) = run_source(
+            "var a = [1, 2];
+            { var b = a; b[0] = b[1] * 10; }
+            var c = a[0];
+            var d = [[a]][0][0][1];",
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(global(&vm, "c"), Some(Value::Number(20.0)));
+        assert_eq!(global(&vm, "d"), Some(Value::Number(2.0)));
+
+        let (_, result) = run_source("var a = [];\n\na[0] = 1;");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "List index out of range.\n[line 3]"
+        );
+    }
+
+    #[test]
+    fn test_maps() {
+        let (vm, result) = run_source(
+            "var m = {\"a\": 1, -0: 2};
+            m[0] = m[\"a\"] + m[0];
+            var a = m[0];
+            var n = values(m);",
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(global(&vm, "a"), Some(Value::Number(3.0)));
+        assert_eq!(global(&vm, "n").unwrap().to_string(), "[1, 3]");
+
+        let (_, result) = run_source("var m = {};\n\nm[0 / 0] = 1;");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Map keys can't be nan.\n[line 3]"
+        );
+    }
+
+    #[test]
+    fn test_classes() {
+        let (vm, result) = run_source(
+            "class Counter {
+              init(start) { this.count = start; }
+              add(n) { this.count = this.count + n; return this; }
+            }
+            class Doubler < Counter {
+              add(n) { return super.add(n * 2); }
+            }
+            var d = Doubler(1).add(2).add(3);
+            var count = d.count;
+            var add = d.add;
+            add(1);
+            var after = d.count;",
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(global(&vm, "count"), Some(Value::Number(11.0)));
+        assert_eq!(global(&vm, "after"), Some(Value::Number(13.0)));
+
+        // The host can make instances too.
+        let mut vm = vm;
+        let counter = vm.call(global(&vm, "Counter").unwrap(), &[Value::Number(4.0)]);
+        assert_eq!(counter.unwrap().to_string(), "Counter instance");
+        assert_eq!(
+            vm.call(global(&vm, "Counter").unwrap(), &[])
+                .unwrap_err()
+                .to_string(),
+            "Expected 1 arguments but got 0."
+        );
+    }
+
+    #[test]
+    fn test_exceptions() {
+        let (vm, result) = run_source(
+            "fun fail(n) { if (n == 0) throw \"bottom\"; return fail(n - 1); }
+            var caught;
+            var total = 0;
+            for (var i = 0; i < 3; i = i + 1) {
+              var doubled = i * 2;
+              try { fail(3); } catch (e) { caught = e; total = total + doubled; }
+            }
+            var after = 1;",
+        );
+
+        assert!(result.is_ok());
+        // The catch unwinds the frames and stack the throw left, so locals still line up.
+        assert_eq!(global(&vm, "caught"), Some(Value::from("bottom")));
+        assert_eq!(global(&vm, "total"), Some(Value::Number(6.0)));
+        assert_eq!(global(&vm, "after"), Some(Value::Number(1.0)));
+
+        let (_, result) = run_source("try { throw 1; } finally {\n  -nil;\n}");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Operand must be a number.\n[line 2]"
+        );
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let message = |source: &str| match run_source(source).1 {
+            Err(err) => err.to_string(),
+            Ok(()) => panic!("Expected a runtime error."),
+        };
+
+        assert_eq!(
+            message("print -\"a\";"),
+            "Operand must be a number.\n[line 1]"
+        );
+        assert_eq!(
+            message("fun f(a) {}\n\nf();"),
+            "Expected 1 arguments but got 0.\n[line 3]"
+        );
+        assert_eq!(
+            message("\"f\"();"),
+            "Can only call functions and classes.\n[line 1]"
+        );
+        assert_eq!(
+            message("fun f() { f(); } f();"),
+            "Stack overflow.\n[line 1]\n  at f (line 1)\n  at f (line 1)\n  at f (line 1)\n  \
+             [previous frame repeated 252 more times]"
+        );
+        assert_eq!(message("undefined = 1;"), "Undefined variable 'undefined'.");
+    }
+
+    #[test]
+    fn test_stack_trace() {
+        let (_, result) = run_source(
+            "fun inner(x) {\n  return -x;\n}\nfun outer() { return inner(\"a\"); }\n\nouter();",
+        );
+        let trace: Vec<String> = result
+            .unwrap_err()
+            .trace()
+            .iter()
+            .map(|frame| frame.to_string())
+            .collect();
+        assert_eq!(trace, vec!["at inner (line 4)", "at outer (line 6)"]);
+    }
+
+    #[test]
+    fn test_define_native() {
+        let tokens = Scanner::new("var a = twice(2);\ntwice(true);".to_string()).scan_tokens();
+        let (statements, _) = Parser::new(tokens).parse();
+        let script = Compiler::new().compile(&statements).unwrap();
+
+        let mut vm = Vm::new();
+        vm.define_native("twice", Arity::Fixed(1), |arguments| {
+            Ok((f64::try_from(arguments[0].clone())? * 2.0).into())
+        });
+
+        let err = vm.interpret(script).unwrap_err();
+        assert_eq!(global(&vm, "a"), Some(Value::Number(4.0)));
+        assert_eq!(err.to_string(), "Expected a number but got true.\n[line 2]");
+    }
+}